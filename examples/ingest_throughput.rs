@@ -0,0 +1,99 @@
+// 多线程写入 CpuAnalyzer 的吞吐基准，同时在另一线程上持续查询/导出其他进程的数据。
+// cargo run --release --example ingest_throughput -- [producer线程数] [每线程事件数]
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use rust_kindling_test::cpuAnalyzer::{CpuAnalyzer, CpuEvent, SegmentQuery, SegmentRecord, SegmentSink};
+
+const TARGET_EVENTS_PER_SECOND: f64 = 500_000.0;
+const BASE_TIME: u64 = 1_700_000_000_000_000_000;
+const PIDS_PER_PRODUCER: u32 = 4;
+const TIDS_PER_PID: u32 = 16;
+// 模拟事件间隔，使每个线程的数据在保留窗口内滚动
+const EVENT_INTERVAL: u64 = 1_000_000;
+const QUERY_PID: u32 = 1_000_000;
+
+struct NullSink;
+
+impl SegmentSink for NullSink {
+    fn send(&mut self, _record: &SegmentRecord) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn cpu_event(start_time: u64) -> Box<CpuEvent> {
+    Box::new(CpuEvent {
+        start_time,
+        end_time: start_time + EVENT_INTERVAL,
+        type_specs: vec![EVENT_INTERVAL / 2, EVENT_INTERVAL / 2],
+        runq_latency: vec![10_000, 0],
+        time_type: vec![0, 3],
+        ..Default::default()
+    })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let producers: u32 = args.get(1).and_then(|arg| arg.parse().ok()).unwrap_or(8);
+    let events_per_producer: u64 = args.get(2).and_then(|arg| arg.parse().ok()).unwrap_or(1_000_000);
+
+    let analyzer = Arc::new(CpuAnalyzer::with_sink(Box::new(NullSink)));
+    for i in 0..1000u64 {
+        analyzer.put_event_to_segments(QUERY_PID, QUERY_PID, "query-target", "", cpu_event(BASE_TIME + i * EVENT_INTERVAL));
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let queries = Arc::new(AtomicU64::new(0));
+    let max_query_micros = Arc::new(AtomicU64::new(0));
+    let query_handle = {
+        let analyzer = Arc::clone(&analyzer);
+        let running = Arc::clone(&running);
+        let queries = Arc::clone(&queries);
+        let max_query_micros = Arc::clone(&max_query_micros);
+        thread::spawn(move || {
+            let query = SegmentQuery { pid: Some(QUERY_PID), ..Default::default() };
+            while running.load(Ordering::Relaxed) {
+                let started = Instant::now();
+                let snapshots = analyzer.query_time_segments(&query);
+                analyzer.send_events(QUERY_PID, BASE_TIME, BASE_TIME + 2 * 1_000_000_000);
+                let elapsed = started.elapsed().as_micros() as u64;
+                max_query_micros.fetch_max(elapsed, Ordering::Relaxed);
+                queries.fetch_add(snapshots.len() as u64, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    };
+
+    let started = Instant::now();
+    let handles: Vec<_> = (0..producers)
+        .map(|producer| {
+            let analyzer = Arc::clone(&analyzer);
+            thread::spawn(move || {
+                for i in 0..events_per_producer {
+                    let pid = producer * PIDS_PER_PRODUCER + (i as u32 % PIDS_PER_PRODUCER) + 1;
+                    let tid = pid * 1000 + (i as u32 / PIDS_PER_PRODUCER) % TIDS_PER_PID;
+                    let start_time = BASE_TIME + (i / (PIDS_PER_PRODUCER * TIDS_PER_PID) as u64) * EVENT_INTERVAL;
+                    analyzer.put_event_to_segments(pid, tid, "worker", "", cpu_event(start_time));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = started.elapsed();
+    running.store(false, Ordering::Relaxed);
+    query_handle.join().unwrap();
+
+    let total = producers as u64 * events_per_producer;
+    let rate = total as f64 / elapsed.as_secs_f64();
+    println!("ingested {} events with {} producers in {:.3}s: {:.0} events/s", total, producers, elapsed.as_secs_f64(), rate);
+    println!("concurrent queries: {} snapshots, max query+export latency {} us", queries.load(Ordering::Relaxed), max_query_micros.load(Ordering::Relaxed));
+    if rate >= TARGET_EVENTS_PER_SECOND {
+        println!("PASS: >= {:.0} events/s", TARGET_EVENTS_PER_SECOND);
+    } else {
+        println!("FAIL: < {:.0} events/s", TARGET_EVENTS_PER_SECOND);
+    }
+}
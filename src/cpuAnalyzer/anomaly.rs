@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::model::{CpuInterval, Segment};
+use crate::cpuAnalyzer::thread_pool::ThreadPoolConfig;
+
+#[derive(Debug, Clone)]
+pub struct AnomalyConfig {
+    // EWMA 的平滑系数，越大越快适应新的常态
+    pub alpha: f64,
+    // 偏离均值超过多少个标准差视为异常
+    pub threshold: f64,
+    // 同一分组至少学习了多少个 segment 后才开始判断
+    pub min_samples: u64,
+    // off cpu 占比与均值的最小偏差，避免方差很小时的误报
+    pub min_off_cpu_ratio_delta: f64,
+    // 平均排队时延与均值的最小偏差(ns)
+    pub min_runq_latency_delta: f64,
+    // 发现异常时是否调用 send_events 导出该时间窗口
+    pub export_on_anomaly: bool,
+    // 线程分组方式，同一线程池内的线程共享基线
+    pub pools: ThreadPoolConfig,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        AnomalyConfig {
+            alpha: 0.05,
+            threshold: 4.0,
+            min_samples: 30,
+            min_off_cpu_ratio_delta: 0.2,
+            min_runq_latency_delta: 1_000_000.0,
+            export_on_anomaly: false,
+            pools: ThreadPoolConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AnomalyMetric {
+    OffCpuRatio,
+    RunqLatency,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Anomaly {
+    pub pid: u32,
+    pub tid: u32,
+    pub thread_name: String,
+    pub group: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub metric: AnomalyMetric,
+    pub value: f64,
+    pub baseline_mean: f64,
+    pub baseline_stddev: f64,
+    // 占用 off cpu 时间最多的类型，以及其中最长一段的描述
+    pub dominant_off_cpu: Option<String>,
+    pub detail: Option<String>,
+}
+
+// 指数加权的均值和方差
+#[derive(Debug, Clone, Default)]
+struct Ewma {
+    mean: f64,
+    variance: f64,
+    samples: u64,
+}
+
+impl Ewma {
+    fn update(&mut self, value: f64, alpha: f64) {
+        if self.samples == 0 {
+            self.mean = value;
+        } else {
+            let diff = value - self.mean;
+            let increment = alpha * diff;
+            self.mean += increment;
+            self.variance = (1.0 - alpha) * (self.variance + diff * increment);
+        }
+        self.samples += 1;
+    }
+
+    fn stddev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+
+    fn is_anomaly(&self, value: f64, config: &AnomalyConfig, min_delta: f64) -> bool {
+        if self.samples < config.min_samples {
+            return false;
+        }
+        let delta = value - self.mean;
+        delta > min_delta && delta > config.threshold * self.stddev()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct GroupBaseline {
+    off_cpu_ratio: Ewma,
+    runq_latency: Ewma,
+}
+
+// 单个 segment 的统计
+struct SegmentMetrics {
+    off_cpu_ratio: f64,
+    runq_latency: Option<f64>,
+    dominant_off_cpu: Option<String>,
+    detail: Option<String>,
+}
+
+impl SegmentMetrics {
+    fn collect(segment: &Segment) -> Option<Self> {
+        let timeline = segment.timeline();
+        let (start_time, end_time) = (segment.start_time(), segment.end_time());
+        let mut on_cpu = 0;
+        let mut off_cpu = 0;
+        let mut off_cpu_by_type: BTreeMap<&'static str, u64> = BTreeMap::new();
+        let mut runq_samples = Vec::new();
+        let mut longest_off: Option<&CpuInterval> = None;
+        for interval in timeline.iter() {
+            let overlap = interval.weighted(interval.overlap(start_time, end_time));
+            if interval.time_type.is_on_cpu() {
+                on_cpu += overlap;
+            } else {
+                off_cpu += overlap;
+                *off_cpu_by_type.entry(interval.time_type.name()).or_insert(0) += overlap;
+                if longest_off.is_none_or(|longest| longest.weighted(longest.overlap(start_time, end_time)) < overlap) {
+                    longest_off = Some(interval);
+                }
+            }
+            if interval.runq_latency > 0 && interval.start_time >= start_time {
+                runq_samples.push((interval.runq_latency as f64, interval.sample_weight));
+            }
+        }
+        if on_cpu + off_cpu == 0 {
+            return None;
+        }
+        Some(SegmentMetrics {
+            off_cpu_ratio: off_cpu as f64 / (on_cpu + off_cpu) as f64,
+            runq_latency: weighted_mean(&runq_samples),
+            dominant_off_cpu: off_cpu_by_type.into_iter().max_by_key(|(_, time)| *time).map(|(name, _)| name.to_string()),
+            detail: longest_off.map(|interval| interval.to_string()),
+        })
+    }
+}
+
+// 按采样权重的平均值，没有样本时为 None
+fn weighted_mean(samples: &[(f64, f64)]) -> Option<f64> {
+    let weight: f64 = samples.iter().map(|(_, weight)| *weight).sum();
+    if weight <= 0.0 {
+        return None;
+    }
+    Some(samples.iter().map(|(value, weight)| value * weight).sum::<f64>() / weight)
+}
+
+// 逐个扫描已完成的 segment，按线程分组学习 off cpu 占比和排队时延的常态，
+// 明显偏高时产生异常事件
+pub struct AnomalyDetector {
+    analyzer: Arc<CpuAnalyzer>,
+    config: AnomalyConfig,
+    baselines: Mutex<HashMap<(u32, String), GroupBaseline>>,
+    // 每个线程已扫描到的时间(ns)
+    scanned: Mutex<HashMap<(u32, u32), u64>>,
+}
+
+impl AnomalyDetector {
+    pub fn new(analyzer: Arc<CpuAnalyzer>, config: AnomalyConfig) -> Self {
+        AnomalyDetector {
+            analyzer,
+            config,
+            baselines: Mutex::new(HashMap::new()),
+            scanned: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 扫描自上次调用以来完成的 segment，返回发现的异常
+    pub fn scan(&self) -> Vec<Anomaly> {
+        let completed_before = self.analyzer.completed_before();
+        let mut anomalies = Vec::new();
+        let mut baselines = self.baselines.lock().unwrap();
+        let mut scanned = self.scanned.lock().unwrap();
+        let mut live_pids = HashSet::new();
+        let mut live_threads = HashSet::new();
+        for (pid, pid_events) in self.analyzer.all_pid_events() {
+            live_pids.insert(pid);
+            let tid_cpu_events = pid_events.lock().unwrap();
+            for time_segments in tid_cpu_events.threads.values() {
+                live_threads.insert((pid, time_segments.tid));
+                let scanned_until = scanned.entry((pid, time_segments.tid)).or_insert(0);
+                for segment in time_segments.segments.iter() {
+                    if segment.start_time() < *scanned_until || segment.end_time() > completed_before {
+                        continue;
+                    }
+                    *scanned_until = segment.end_time();
+                    let metrics = match SegmentMetrics::collect(segment) {
+                        Some(metrics) => metrics,
+                        None => continue,
+                    };
+                    let thread_name = time_segments.thread_name_at(segment.start_time()).to_string();
+                    let group = self.config.pools.pool_name(&thread_name);
+                    let baseline = baselines.entry((pid, group.clone())).or_default();
+                    let mut detected = Vec::new();
+                    if baseline.off_cpu_ratio.is_anomaly(metrics.off_cpu_ratio, &self.config, self.config.min_off_cpu_ratio_delta) {
+                        detected.push((AnomalyMetric::OffCpuRatio, metrics.off_cpu_ratio, baseline.off_cpu_ratio.clone()));
+                    }
+                    baseline.off_cpu_ratio.update(metrics.off_cpu_ratio, self.config.alpha);
+                    if let Some(runq_latency) = metrics.runq_latency {
+                        if baseline.runq_latency.is_anomaly(runq_latency, &self.config, self.config.min_runq_latency_delta) {
+                            detected.push((AnomalyMetric::RunqLatency, runq_latency, baseline.runq_latency.clone()));
+                        }
+                        baseline.runq_latency.update(runq_latency, self.config.alpha);
+                    }
+                    for (metric, value, ewma) in detected {
+                        anomalies.push(Anomaly {
+                            pid,
+                            tid: time_segments.tid,
+                            thread_name: thread_name.clone(),
+                            group: group.clone(),
+                            start_time: segment.start_time(),
+                            end_time: segment.end_time(),
+                            metric,
+                            value,
+                            baseline_mean: ewma.mean,
+                            baseline_stddev: ewma.stddev(),
+                            dominant_off_cpu: metrics.dominant_off_cpu.clone(),
+                            detail: metrics.detail.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        // 已退出或被淘汰的线程和进程不再保留状态
+        scanned.retain(|key, _| live_threads.contains(key));
+        baselines.retain(|(pid, _), _| live_pids.contains(pid));
+        drop(scanned);
+        drop(baselines);
+
+        if self.config.export_on_anomaly {
+            for anomaly in anomalies.iter() {
+                self.analyzer.send_events(anomaly.pid, anomaly.start_time, anomaly.end_time - 1);
+            }
+        }
+        anomalies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cpu_analyzer::{CpuAnalyzerConfig, NANO_TO_SECONDS};
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+    const MS: u64 = 1_000_000;
+
+    fn config() -> AnomalyConfig {
+        AnomalyConfig {
+            min_samples: 5,
+            ..Default::default()
+        }
+    }
+
+    fn setup(config: AnomalyConfig) -> (Arc<CpuAnalyzer>, AnomalyDetector) {
+        let cca = Arc::new(CpuAnalyzer::with_config(CpuAnalyzerConfig::default(), Box::new(MemorySink::new())));
+        let detector = AnomalyDetector::new(Arc::clone(&cca), config);
+        (cca, detector)
+    }
+
+    // 第 second 秒内先 on cpu 再读文件，off_ms 为 off cpu 的毫秒数，runq_ms 为开始前的排队时延
+    fn put_second(cca: &CpuAnalyzer, tid: u32, second: u64, off_ms: u64, runq_ms: u64) {
+        let start_time = (BASE + second) * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID, tid, "http-nio-8080-exec-1", "", Box::new(CpuEvent {
+            start_time,
+            end_time: start_time + 1000 * MS,
+            type_specs: vec![(1000 - off_ms) * MS, off_ms * MS],
+            runq_latency: vec![runq_ms * MS, 0],
+            time_type: vec![0, 1],
+            off_info: "read;file=/data/a".to_string(),
+            ..Default::default()
+        }));
+    }
+
+    fn put_normal_seconds(cca: &CpuAnalyzer, seconds: std::ops::Range<u64>) {
+        for second in seconds {
+            put_second(cca, 10, second, 100, 0);
+        }
+    }
+
+    #[test]
+    fn no_detection_before_min_samples() {
+        let (cca, detector) = setup(config());
+        put_normal_seconds(&cca, 0..3);
+        put_second(&cca, 10, 3, 900, 0);
+        // 之后的事件推进水位，使前面的 segment 完成
+        put_second(&cca, 10, 4, 100, 0);
+        assert!(detector.scan().is_empty());
+    }
+
+    #[test]
+    fn off_cpu_ratio_anomaly_is_detected() {
+        let (cca, detector) = setup(config());
+        put_normal_seconds(&cca, 0..10);
+        // 偏差不足 min_off_cpu_ratio_delta 不算异常
+        put_second(&cca, 10, 10, 250, 0);
+        put_second(&cca, 10, 11, 900, 0);
+        put_second(&cca, 10, 12, 100, 0);
+
+        let anomalies = detector.scan();
+        assert_eq!(anomalies.len(), 1);
+        let anomaly = &anomalies[0];
+        assert_eq!(anomaly.metric, AnomalyMetric::OffCpuRatio);
+        assert_eq!(anomaly.start_time, (BASE + 11) * NANO_TO_SECONDS);
+        assert_eq!(anomaly.group, "http-nio-8080-exec");
+        assert!((anomaly.value - 0.9).abs() < 1e-9);
+        assert!(anomaly.baseline_mean < 0.15);
+        assert_eq!(anomaly.dominant_off_cpu.as_deref(), Some("file"));
+    }
+
+    #[test]
+    fn runq_latency_anomaly_is_detected() {
+        let (cca, detector) = setup(config());
+        for second in 0..10 {
+            put_second(&cca, 10, second, 100, 1);
+        }
+        put_second(&cca, 10, 10, 100, 20);
+        put_second(&cca, 10, 11, 100, 1);
+
+        let anomalies = detector.scan();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].metric, AnomalyMetric::RunqLatency);
+        assert_eq!(anomalies[0].start_time, (BASE + 10) * NANO_TO_SECONDS);
+    }
+
+    #[test]
+    fn segments_are_scanned_once() {
+        let (cca, detector) = setup(config());
+        put_normal_seconds(&cca, 0..10);
+        put_second(&cca, 10, 10, 900, 0);
+        put_second(&cca, 10, 11, 100, 0);
+        assert_eq!(detector.scan().len(), 1);
+        assert!(detector.scan().is_empty());
+
+        put_second(&cca, 10, 12, 900, 0);
+        put_second(&cca, 10, 13, 100, 0);
+        let anomalies = detector.scan();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].start_time, (BASE + 12) * NANO_TO_SECONDS);
+    }
+
+    #[test]
+    fn state_of_evicted_threads_is_dropped() {
+        let (cca, detector) = setup(config());
+        put_normal_seconds(&cca, 0..3);
+        put_second(&cca, 11, 0, 100, 0);
+        put_second(&cca, 11, 3, 100, 0);
+        detector.scan();
+        assert_eq!(detector.scanned.lock().unwrap().len(), 2);
+
+        cca.pid_events(PID).unwrap().lock().unwrap().threads.remove(&11);
+        detector.scan();
+        let scanned = detector.scanned.lock().unwrap();
+        assert_eq!(scanned.keys().collect::<Vec<_>>(), vec![&(PID, 10)]);
+        assert_eq!(detector.baselines.lock().unwrap().len(), 1);
+    }
+}
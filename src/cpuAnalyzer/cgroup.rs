@@ -0,0 +1,261 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_derive::{Deserialize, Serialize};
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::time_event::{register_event_kind, TimedEvent};
+
+pub const CGROUP_THROTTLE_EVENT_KIND: &str = "cgroup_throttle";
+
+// 两次采样之间 cgroup 被 CFS 限流的情况，放入进程内每个线程与之重叠的 segment
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CgroupThrottleEvent {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub cgroup: String,
+    pub nr_periods: u64,
+    pub nr_throttled: u64,
+    // 采样间隔内被限流的总时长(ns)
+    pub throttled_time: u64,
+}
+
+impl CgroupThrottleEvent {
+    // 与 [start_time, end_time) 重叠部分按比例分摊的限流时长
+    pub fn throttled_time_in(&self, start_time: u64, end_time: u64) -> u64 {
+        let overlap = self.end_time.min(end_time).saturating_sub(self.start_time.max(start_time));
+        let duration = self.end_time.saturating_sub(self.start_time);
+        if duration == 0 {
+            return 0;
+        }
+        (self.throttled_time as u128 * overlap as u128 / duration as u128) as u64
+    }
+}
+
+impl TimedEvent for CgroupThrottleEvent {
+    fn start_timestamp(&self) -> u64 {
+        self.start_time
+    }
+
+    fn end_timestamp(&self) -> u64 {
+        self.end_time
+    }
+
+    fn kind(&self) -> &'static str {
+        CGROUP_THROTTLE_EVENT_KIND
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.cgroup.capacity()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// cpu.stat 中的累计值，v1 的 throttled_time 为 ns，v2 的 throttled_usec 为 us
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CpuStat {
+    pub nr_periods: u64,
+    pub nr_throttled: u64,
+    pub throttled_time: u64,
+}
+
+impl CpuStat {
+    pub fn parse(content: &str) -> Self {
+        let mut stat = CpuStat::default();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let (key, value) = match (fields.next(), fields.next().and_then(|value| value.parse::<u64>().ok())) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            match key {
+                "nr_periods" => stat.nr_periods = value,
+                "nr_throttled" => stat.nr_throttled = value,
+                "throttled_time" => stat.throttled_time = value,
+                "throttled_usec" => stat.throttled_time = value * 1000,
+                _ => (),
+            }
+        }
+        stat
+    }
+}
+
+// 由 /proc/<pid>/cgroup 找到进程所在 cgroup 的 cpu.stat，
+// v2 为 "0::/path"，v1 为包含 cpu 控制器的那一行
+pub fn cpu_stat_path(cgroup_root: &Path, proc_cgroup: &str) -> Option<(String, PathBuf)> {
+    let mut unified = None;
+    for line in proc_cgroup.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (hierarchy, controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(hierarchy), Some(controllers), Some(path)) => (hierarchy, controllers, path),
+            _ => continue,
+        };
+        let relative = path.trim_start_matches('/');
+        if controllers.split(',').any(|controller| controller == "cpu") {
+            let candidates = [cgroup_root.join(controllers), cgroup_root.join("cpu"), cgroup_root.join("cpu,cpuacct")];
+            if let Some(dir) = candidates.iter().find(|dir| dir.join(relative).join("cpu.stat").exists()) {
+                return Some((path.to_string(), dir.join(relative).join("cpu.stat")));
+            }
+        } else if hierarchy == "0" && controllers.is_empty() {
+            unified = Some((path.to_string(), cgroup_root.join(relative).join("cpu.stat")));
+        }
+    }
+    unified.filter(|(_, stat_path)| stat_path.exists())
+}
+
+struct CgroupSample {
+    cgroup: String,
+    stat_path: PathBuf,
+    stat: CpuStat,
+    timestamp: u64,
+}
+
+// 定期读取每个被跟踪进程的 cgroup cpu.stat，将两次采样间的限流情况作为事件放入 segment
+pub struct CgroupCollector {
+    analyzer: Arc<CpuAnalyzer>,
+    proc_root: PathBuf,
+    cgroup_root: PathBuf,
+    samples: Mutex<HashMap<u32, CgroupSample>>,
+}
+
+impl CgroupCollector {
+    pub fn new(analyzer: Arc<CpuAnalyzer>) -> Self {
+        Self::with_roots(analyzer, PathBuf::from("/proc"), PathBuf::from("/sys/fs/cgroup"))
+    }
+
+    // 创建时注册限流事件类型，快照中的限流事件需在此之后加载才能恢复
+    pub fn with_roots(analyzer: Arc<CpuAnalyzer>, proc_root: PathBuf, cgroup_root: PathBuf) -> Self {
+        register_event_kind::<CgroupThrottleEvent>(CGROUP_THROTTLE_EVENT_KIND);
+        CgroupCollector {
+            analyzer,
+            proc_root,
+            cgroup_root,
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 采样一次，返回新产生的限流事件数
+    pub fn collect(&self) -> usize {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as u64).unwrap_or(0);
+        let pids = self.analyzer.pids();
+        let mut samples = self.samples.lock().unwrap();
+        samples.retain(|pid, _| pids.binary_search(pid).is_ok());
+        let mut throttled = 0;
+        for pid in pids {
+            let previous = samples.remove(&pid);
+            let (cgroup, stat_path) = match &previous {
+                Some(sample) => (sample.cgroup.clone(), sample.stat_path.clone()),
+                None => {
+                    let proc_cgroup = match fs::read_to_string(self.proc_root.join(pid.to_string()).join("cgroup")) {
+                        Ok(content) => content,
+                        Err(_) => continue,
+                    };
+                    match cpu_stat_path(&self.cgroup_root, &proc_cgroup) {
+                        Some(found) => found,
+                        None => continue,
+                    }
+                }
+            };
+            let stat = match fs::read_to_string(&stat_path) {
+                Ok(content) => CpuStat::parse(&content),
+                // 进程可能已退出或被移到其他 cgroup，下次重新查找
+                Err(_) => continue,
+            };
+            if let Some(previous) = previous {
+                let nr_throttled = stat.nr_throttled.saturating_sub(previous.stat.nr_throttled);
+                if nr_throttled > 0 {
+                    let event = CgroupThrottleEvent {
+                        start_time: previous.timestamp,
+                        end_time: now,
+                        cgroup: cgroup.clone(),
+                        nr_periods: stat.nr_periods.saturating_sub(previous.stat.nr_periods),
+                        nr_throttled,
+                        throttled_time: stat.throttled_time.saturating_sub(previous.stat.throttled_time),
+                    };
+                    self.analyzer.put_process_event(pid, Arc::new(event));
+                    throttled += 1;
+                }
+            }
+            samples.insert(pid, CgroupSample { cgroup, stat_path, stat, timestamp: now });
+        }
+        throttled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每个测试使用独立的临时目录，结束时删除
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("cgroup-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn touch(&self, relative: &str) -> PathBuf {
+            let path = self.0.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parse_cpu_stat_v1() {
+        let stat = CpuStat::parse("nr_periods 100\nnr_throttled 7\nthrottled_time 123456789\n");
+        assert_eq!(stat, CpuStat { nr_periods: 100, nr_throttled: 7, throttled_time: 123_456_789 });
+    }
+
+    #[test]
+    fn parse_cpu_stat_v2() {
+        let stat = CpuStat::parse("usage_usec 999\nuser_usec 1\nnr_periods 20\nnr_throttled 3\nthrottled_usec 1500\nbad line\n");
+        assert_eq!(stat, CpuStat { nr_periods: 20, nr_throttled: 3, throttled_time: 1_500_000 });
+    }
+
+    #[test]
+    fn cpu_stat_path_v2() {
+        let root = TempDir::new("v2");
+        let stat_path = root.touch("kubepods/pod1/cpu.stat");
+        assert_eq!(cpu_stat_path(&root.0, "0::/kubepods/pod1\n"), Some(("/kubepods/pod1".to_string(), stat_path)));
+        assert_eq!(cpu_stat_path(&root.0, "0::/missing\n"), None);
+    }
+
+    #[test]
+    fn cpu_stat_path_v1() {
+        let root = TempDir::new("v1");
+        let stat_path = root.touch("cpu,cpuacct/docker/abc/cpu.stat");
+        let proc_cgroup = "12:memory:/docker/abc\n4:cpuacct,cpu:/docker/abc\n0::/\n";
+        // 控制器目录名与 /proc 中的顺序不同时回退到 cpu,cpuacct
+        assert_eq!(cpu_stat_path(&root.0, proc_cgroup), Some(("/docker/abc".to_string(), stat_path)));
+
+        let stat_path = root.touch("cpuacct,cpu/docker/abc/cpu.stat");
+        assert_eq!(cpu_stat_path(&root.0, proc_cgroup), Some(("/docker/abc".to_string(), stat_path)));
+    }
+
+    #[test]
+    fn throttled_time_is_prorated() {
+        let event = CgroupThrottleEvent { start_time: 100, end_time: 200, throttled_time: 50, ..Default::default() };
+        assert_eq!(event.throttled_time_in(150, 300), 25);
+        assert_eq!(event.throttled_time_in(300, 400), 0);
+    }
+}
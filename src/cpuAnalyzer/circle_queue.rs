@@ -1,8 +1,11 @@
+use serde_derive::{Deserialize, Serialize};
 use crate::cpuAnalyzer::model::Segment;
 
-#[derive(Debug)]
+// 环形队列，下标均为相对 head 的逻辑下标，0 对应最早的 segment
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CircleQueue {
     length: usize,
+    head: usize,
     data: Vec<Segment>,
 }
 
@@ -10,26 +13,130 @@ impl CircleQueue {
     pub fn new(length: usize) -> Self {
         CircleQueue {
             length,
+            head: 0,
             data: vec![Default::default(); length],
         }
     }
 
+    // 反序列化得到的队列需检查，否则下标计算会越界
+    pub fn is_consistent(&self, length: usize) -> bool {
+        self.length == length && self.data.len() == length && (self.head < length || length == 0)
+    }
+
+    fn physical_index(&self, index: usize) -> usize {
+        (self.head + index) % self.length
+    }
+
     pub fn get_by_index(&self, index: usize) -> Option<&Segment> {
-        self.data.get(index)
+        if index >= self.length {
+            return None;
+        }
+        self.data.get(self.physical_index(index))
     }
 
     pub fn get_by_index_mut(&mut self, index: usize) -> Option<&mut Segment> {
-        self.data.get_mut(index)
+        if index >= self.length {
+            return None;
+        }
+        let physical_index = self.physical_index(index);
+        self.data.get_mut(physical_index)
     }
 
-
     pub fn update_by_index(&mut self, index: usize, val: Segment) {
         if index < self.length {
-            self.data[index] = val;
+            let physical_index = self.physical_index(index);
+            self.data[physical_index] = val;
+        }
+    }
+
+    // 按逻辑顺序遍历
+    pub fn iter(&self) -> impl Iterator<Item = &Segment> + '_ {
+        let (tail, head) = self.data.split_at(self.head);
+        head.iter().chain(tail.iter())
+    }
+
+    // 丢弃最早的 count 个 segment，移动 head 后由 new_segment 按新的逻辑下标生成尾部的 segment
+    pub fn advance<F>(&mut self, count: usize, new_segment: F) where F: Fn(usize) -> Segment {
+        let count = count.min(self.length);
+        for i in 0..count {
+            let physical_index = self.physical_index(i);
+            self.data[physical_index] = new_segment(self.length - count + i);
         }
+        self.head = self.physical_index(count);
     }
 
-    pub fn clear(&mut self) {
-        self.data = vec![Default::default(); self.length];
+    // 原地重置所有 segment，不重新分配
+    pub fn reset<F>(&mut self, new_segment: F) where F: Fn(usize) -> Segment {
+        self.head = 0;
+        for (i, segment) in self.data.iter_mut().enumerate() {
+            *segment = new_segment(i);
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(queue: &CircleQueue) -> Vec<u64> {
+        queue.iter().map(|segment| segment.start_time()).collect()
+    }
+
+    fn new_queue(length: usize) -> CircleQueue {
+        let mut queue = CircleQueue::new(length);
+        queue.reset(|i| Segment::new(i as u64, i as u64 + 1));
+        queue
+    }
+
+    #[test]
+    fn iter_follows_logical_order() {
+        let queue = new_queue(4);
+        assert_eq!(starts(&queue), vec![0, 1, 2, 3]);
+        assert_eq!(queue.get_by_index(2).unwrap().start_time(), 2);
+        assert!(queue.get_by_index(4).is_none());
+    }
+
+    #[test]
+    fn advance_wraps_around() {
+        let mut queue = new_queue(4);
+        let mut base = 0;
+        // 多次前移，head 越过数组末尾后仍按时间顺序排列
+        for count in [1, 2, 3, 2] {
+            base += count as u64;
+            queue.advance(count, |i| Segment::new(base + i as u64, base + i as u64 + 1));
+            assert_eq!(starts(&queue), (base..base + 4).collect::<Vec<u64>>());
+            for i in 0..4 {
+                assert_eq!(queue.get_by_index(i).unwrap().start_time(), base + i as u64);
+            }
+        }
+        assert!(queue.is_consistent(4));
+    }
+
+    #[test]
+    fn advance_more_than_length_replaces_everything() {
+        let mut queue = new_queue(3);
+        queue.advance(10, |i| Segment::new(100 + i as u64, 101 + i as u64));
+        assert_eq!(starts(&queue), vec![100, 101, 102]);
+    }
+
+    #[test]
+    fn reset_restarts_from_head_zero() {
+        let mut queue = new_queue(4);
+        queue.advance(3, |i| Segment::new(3 + i as u64, 4 + i as u64));
+        queue.reset(|i| Segment::new(50 + i as u64, 51 + i as u64));
+        assert_eq!(starts(&queue), vec![50, 51, 52, 53]);
+        queue.update_by_index(1, Segment::new(7, 8));
+        assert_eq!(queue.get_by_index(1).unwrap().start_time(), 7);
+    }
+
+    #[test]
+    fn inconsistent_queue_is_detected() {
+        let queue: CircleQueue = serde_json::from_value(serde_json::json!({
+            "length": 40,
+            "head": 7,
+            "data": [Segment::new(0, 1)],
+        })).unwrap();
+        assert!(!queue.is_consistent(40));
+        assert!(!new_queue(4).is_consistent(40));
+    }
+}
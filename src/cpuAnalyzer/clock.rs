@@ -0,0 +1,231 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use serde_derive::{Deserialize, Serialize};
+use crate::cpuAnalyzer::cpu_analyzer::NANO_TO_SECONDS;
+
+// 事件时间戳字段所在的时钟域
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClockDomain {
+    // CLOCK_REALTIME，墙上时间，会被 NTP 调整
+    #[default]
+    Realtime,
+    // CLOCK_MONOTONIC，不含系统休眠时间
+    Monotonic,
+    // CLOCK_BOOTTIME，包含系统休眠时间
+    BootTime,
+}
+
+// 各来源字段的时钟域，以及换算结果的合理范围
+#[derive(Debug, Clone)]
+pub struct ClockConfig {
+    // CpuEvent 的 start_time/end_time
+    pub cpu_event: ClockDomain,
+    // JavaFutexEvent 的开始时间，取自 kindling 事件的 timestamp
+    pub java_futex_start: ClockDomain,
+    // JavaFutexEvent 的结束时间，取自 end_time 字符串参数
+    pub java_futex_end: ClockDomain,
+    // 重新测量时钟偏移的间隔(ns)，用于跟上 NTP 的跳变
+    pub refresh_interval: u64,
+    // 换算后的时间早于当前时间超过该值(ns)即认为无法对齐
+    pub max_past: u64,
+    // 换算后的时间晚于当前时间超过该值(ns)即认为无法对齐
+    pub max_future: u64,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            cpu_event: ClockDomain::Realtime,
+            java_futex_start: ClockDomain::Realtime,
+            java_futex_end: ClockDomain::Realtime,
+            refresh_interval: 10_000_000_000,
+            max_past: 3_600_000_000_000,
+            max_future: 60_000_000_000,
+        }
+    }
+}
+
+// 将各时钟域的时间换算为 realtime(ns)。偏移通过同时读取两个时钟测得，
+// 超过 refresh_interval 后在下一次换算时重新测量
+pub struct ClockSync {
+    config: ClockConfig,
+    measured: AtomicBool,
+    // realtime - monotonic
+    monotonic_offset: AtomicI64,
+    // realtime - boottime
+    boottime_offset: AtomicI64,
+    // 上次测量时的 monotonic 时间
+    last_refresh: AtomicU64,
+}
+
+fn clock_now(clock: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime(clock, &mut ts);
+    }
+    ts.tv_sec as u64 * NANO_TO_SECONDS + ts.tv_nsec as u64
+}
+
+// 在两次读取 clock 之间读取 realtime，取间隔最小的一次，偏移误差不超过该间隔的一半
+fn measure_offset(clock: libc::clockid_t) -> i64 {
+    let mut best_width = u64::MAX;
+    let mut best_offset = 0;
+    for _ in 0..3 {
+        let before = clock_now(clock);
+        let realtime = clock_now(libc::CLOCK_REALTIME);
+        let after = clock_now(clock);
+        let width = after.saturating_sub(before);
+        if width < best_width {
+            best_width = width;
+            best_offset = realtime as i64 - (before + width / 2) as i64;
+        }
+    }
+    best_offset
+}
+
+impl ClockSync {
+    pub fn new(config: ClockConfig) -> Self {
+        ClockSync {
+            config,
+            measured: AtomicBool::new(false),
+            monotonic_offset: AtomicI64::new(0),
+            boottime_offset: AtomicI64::new(0),
+            last_refresh: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &ClockConfig {
+        &self.config
+    }
+
+    // 当前的 realtime(ns)
+    pub fn now(&self) -> u64 {
+        clock_now(libc::CLOCK_REALTIME)
+    }
+
+    pub fn refresh(&self) {
+        self.monotonic_offset.store(measure_offset(libc::CLOCK_MONOTONIC), Ordering::Relaxed);
+        self.boottime_offset.store(measure_offset(libc::CLOCK_BOOTTIME), Ordering::Relaxed);
+        self.last_refresh.store(clock_now(libc::CLOCK_MONOTONIC), Ordering::Relaxed);
+        self.measured.store(true, Ordering::Release);
+    }
+
+    fn refresh_if_stale(&self) {
+        let now = clock_now(libc::CLOCK_MONOTONIC);
+        if !self.measured.load(Ordering::Acquire)
+            || now.saturating_sub(self.last_refresh.load(Ordering::Relaxed)) >= self.config.refresh_interval {
+            self.refresh();
+        }
+    }
+
+    pub fn offset(&self, domain: ClockDomain) -> i64 {
+        match domain {
+            ClockDomain::Realtime => 0,
+            ClockDomain::Monotonic => self.monotonic_offset.load(Ordering::Relaxed),
+            ClockDomain::BootTime => self.boottime_offset.load(Ordering::Relaxed),
+        }
+    }
+
+    // 只加上偏移，不检查结果是否合理
+    pub fn convert(&self, timestamp: u64, domain: ClockDomain) -> i64 {
+        if domain != ClockDomain::Realtime {
+            self.refresh_if_stale();
+        }
+        timestamp as i64 + self.offset(domain)
+    }
+
+    // 换算为 realtime，结果不在 [now - max_past, now + max_future] 内时返回 None
+    pub fn to_realtime(&self, timestamp: u64, domain: ClockDomain) -> Option<u64> {
+        let realtime = self.convert(timestamp, domain);
+        if realtime <= 0 {
+            return None;
+        }
+        let realtime = realtime as u64;
+        let now = clock_now(libc::CLOCK_REALTIME);
+        if realtime + self.config.max_past < now || realtime > now + self.config.max_future {
+            return None;
+        }
+        Some(realtime)
+    }
+
+    // 换算一对起止时间，任一端无法对齐或结束早于开始时返回 None
+    pub fn to_realtime_range(&self, start: u64, start_domain: ClockDomain, end: u64, end_domain: ClockDomain) -> Option<(u64, u64)> {
+        let start = self.to_realtime(start, start_domain)?;
+        let end = self.to_realtime(end, end_domain)?;
+        if end < start {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    // 无法换算的起止时间：以当前时间作为结束时间，保留尽量换算后的时长，但开始时间不早于
+    // 当前这一秒的开始，估计出的事件只落在最新的一个 segment 内，不会铺满线程已有的窗口
+    pub fn estimate_range(&self, start: u64, start_domain: ClockDomain, end: u64, end_domain: ClockDomain) -> (u64, u64) {
+        let duration = self.convert(end, end_domain).saturating_sub(self.convert(start, start_domain)).max(0) as u64;
+        let now = clock_now(libc::CLOCK_REALTIME);
+        let segment_start = now - now % NANO_TO_SECONDS;
+        (now.saturating_sub(duration).max(segment_start), now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = NANO_TO_SECONDS;
+
+    fn clock() -> ClockSync {
+        ClockSync::new(ClockConfig::default())
+    }
+
+    #[test]
+    fn realtime_range_within_bounds() {
+        let clock = clock();
+        let now = clock_now(libc::CLOCK_REALTIME);
+        let start = now - 10 * SECOND;
+        assert_eq!(clock.to_realtime_range(start, ClockDomain::Realtime, now, ClockDomain::Realtime), Some((start, now)));
+    }
+
+    #[test]
+    fn unreasonable_ranges_are_rejected() {
+        let clock = clock();
+        let now = clock_now(libc::CLOCK_REALTIME);
+        let config = clock.config().clone();
+        // 结束早于开始
+        assert_eq!(clock.to_realtime_range(now, ClockDomain::Realtime, now - SECOND, ClockDomain::Realtime), None);
+        // 过早或过晚
+        assert_eq!(clock.to_realtime_range(0, ClockDomain::Realtime, now, ClockDomain::Realtime), None);
+        assert_eq!(clock.to_realtime_range(now - config.max_past - SECOND, ClockDomain::Realtime, now, ClockDomain::Realtime), None);
+        assert_eq!(clock.to_realtime_range(now, ClockDomain::Realtime, now + config.max_future + SECOND, ClockDomain::Realtime), None);
+    }
+
+    #[test]
+    fn monotonic_is_converted_to_realtime() {
+        let clock = clock();
+        let monotonic = clock_now(libc::CLOCK_MONOTONIC);
+        let realtime = clock_now(libc::CLOCK_REALTIME);
+        let (start, end) = clock.to_realtime_range(monotonic, ClockDomain::Monotonic, monotonic + 500, ClockDomain::Monotonic).unwrap();
+        assert_eq!(end - start, 500);
+        assert!(start.abs_diff(realtime) < SECOND / 10);
+    }
+
+    #[test]
+    fn estimate_stays_within_current_segment() {
+        let clock = clock();
+        let before = clock_now(libc::CLOCK_REALTIME);
+        // 开始时间为 0 的事件不能被估计成覆盖整个窗口的长区间
+        let (start, end) = clock.estimate_range(0, ClockDomain::Realtime, before, ClockDomain::Realtime);
+        let after = clock_now(libc::CLOCK_REALTIME);
+        assert!(before <= end && end <= after);
+        assert_eq!(start, end - end % SECOND);
+    }
+
+    #[test]
+    fn estimate_keeps_short_duration() {
+        let clock = clock();
+        let (start, end) = clock.estimate_range(1_000, ClockDomain::Monotonic, 1_200, ClockDomain::Monotonic);
+        // 当前这一秒刚开始时时长会被截断
+        assert!(end - start == 200 || start == end - end % SECOND);
+        let (start, end) = clock.estimate_range(1_200, ClockDomain::Realtime, 1_000, ClockDomain::Realtime);
+        assert_eq!(start, end);
+    }
+}
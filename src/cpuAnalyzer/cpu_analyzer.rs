@@ -2,31 +2,99 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io::Cursor;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
 use std::time::{Duration, Instant, SystemTime};
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
-use chrono::{DateTime, Local};
+use chrono::{DateTime, FixedOffset, Local};
 use libc::time;
 use crate::cpuAnalyzer::model::{CpuEvent, JavaFutexEvent, TimeSegments};
 use crate::probeToRust::KindlingEventForGo;
 use crate::cpuAnalyzer::circle_queue::CircleQueue;
 use crate::cpuAnalyzer::time_event::TimedEvent;
 use crate::cpuAnalyzer::model::Segment;
+use crate::cpuAnalyzer::reorder::{AnalyzerCounters, AnalyzerStats, PendingEvent, ReorderBuffer};
+use crate::cpuAnalyzer::sink::{SegmentRecord, SegmentSink, StdoutJsonSink};
+use crate::cpuAnalyzer::clock::{ClockConfig, ClockSync};
+use crate::cpuAnalyzer::rate_limit::{PidSampler, RateLimitConfig, TokenBucket};
+use crate::cpuAnalyzer::anomaly::Anomaly;
 
-const NANO_TO_SECONDS: u64 = 1_000_000_000;
-const MAX_SEGMENT_SIZE: usize = 40;
+pub(crate) const NANO_TO_SECONDS: u64 = 1_000_000_000;
+pub(crate) const MAX_SEGMENT_SIZE: usize = 40;
 
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub force_resend: bool,
+    // 只导出这些类型的事件(见 TimedEvent::kind)，为空时导出所有类型
+    pub kinds: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CpuAnalyzerConfig {
+    // 事件在线程的重排序缓冲中等待的时间(ns)，用于容忍来自其他 CPU 的迟到事件，0 表示不缓冲
+    pub reorder_delay: u64,
+    // 各来源时间戳的时钟域
+    pub clock: ClockConfig,
+    // 导出记录中 index_local_timestamp 使用的时区，相对 UTC 东偏的秒数
+    pub index_utc_offset: i32,
+    // 放入 segment 前的限流采样
+    pub rate_limit: RateLimitConfig,
+    // 所有线程的事件最多占用的内存(字节)，0 表示不限制
+    pub memory_budget: usize,
+}
+
+// 同一进程下按 tid 组织的线程数据，以及尚未放入 segment 的重排序缓冲
+#[derive(Default)]
+pub struct ThreadSegments {
+    pub threads: HashMap<u32, TimeSegments>,
+    pending: HashMap<u32, ReorderBuffer>,
+    sampler: PidSampler,
+    // 进程级事件由进程持有并只计一次内存，各线程的 segment 中只保存引用
+    process_events: Vec<Arc<dyn TimedEvent>>,
+}
+
+impl ThreadSegments {
+    // 释放已不在任何线程窗口内的进程级事件，返回释放的字节数
+    fn release_expired_process_events(&mut self) -> usize {
+        let window_start = self.threads.values()
+            .map(|time_segments| time_segments.base_time * NANO_TO_SECONDS)
+            .min()
+            .unwrap_or(u64::MAX);
+        let mut released = 0;
+        self.process_events.retain(|event| {
+            let expired = event.end_timestamp() <= window_start;
+            if expired {
+                released += event.size_bytes();
+            }
+            !expired
+        });
+        released
+    }
+}
+
+// 每个进程单独加锁，外层读写锁只在新增进程时加写锁，
+// 因此一个繁忙进程的写入不会阻塞其他进程的导出与查询
 pub struct CpuAnalyzer {
-    pub cpu_pid_events: HashMap<u32, HashMap<u32, TimeSegments>>,
+    config: CpuAnalyzerConfig,
+    cpu_pid_events: RwLock<HashMap<u32, Arc<Mutex<ThreadSegments>>>>,
+    sink: Mutex<Box<dyn SegmentSink>>,
+    // 所有线程中见过的最晚事件开始时间，不超过收到事件时的当前时间，避免时钟超前的进程
+    // 提前放出其他线程缓冲中的事件。只用于不再产生事件的线程，各线程放入时使用自己的水位
+    latest_event_time: AtomicU64,
+    pub(crate) counters: AnalyzerCounters,
+    // 探针时间戳到 realtime 的换算
+    clock: ClockSync,
+    // 全局限流的令牌桶，未配置全局速率时为 None
+    global_bucket: Option<Mutex<TokenBucket>>,
+    eviction_lock: Mutex<()>,
 }
 
-pub fn print_all_event(cca: &Arc<Mutex<CpuAnalyzer>>) {
-    let ca_guard = cca.lock().unwrap();
-    ca_guard.print_cpu_pid_events();
+pub fn print_all_event(cca: &Arc<CpuAnalyzer>) {
+    cca.print_cpu_pid_events();
 }
 
-pub fn consume_cpu_event(event: &KindlingEventForGo, cca: &Arc<Mutex<CpuAnalyzer>>) {
+pub fn consume_cpu_event(event: &KindlingEventForGo, cca: &Arc<CpuAnalyzer>) {
     let mut ev = Box::new(CpuEvent::default());
     for i in 0..event.paramsNumber as usize {
         let user_attributes = event.userAttributes[i];
@@ -53,125 +121,336 @@ pub fn consume_cpu_event(event: &KindlingEventForGo, cca: &Arc<Mutex<CpuAnalyzer
         }
     }
 
-    if ev.start_time < 1600000000000000000 {
-        return;
-    }
+    let domain = cca.config.clock.cpu_event;
+    let range = cca.clock.to_realtime_range(ev.start_time, domain, ev.end_time, domain);
+    ev.clock_unreconciled = range.is_none();
+    let (start_time, end_time) = range.unwrap_or_else(|| {
+        cca.counters.clock_unreconciled.fetch_add(1, Ordering::Relaxed);
+        ev.raw_start_time = Some(ev.start_time);
+        ev.raw_end_time = Some(ev.end_time);
+        cca.clock.estimate_range(ev.start_time, domain, ev.end_time, domain)
+    });
+    ev.start_time = start_time;
+    ev.end_time = end_time;
 
     //println!("{}", ev);
 
-    let mut ca_guard = cca.lock().unwrap();
-    ca_guard.put_event_to_segments(
+    cca.put_event_to_segments(
         event.get_pid(),
         event.get_tid(),
         &event.get_comm(),
+        &event.get_container_id(),
         ev,
     );
 }
 
-pub fn consume_java_futex_event(event: &KindlingEventForGo, cca: &Arc<Mutex<CpuAnalyzer>>) {
+pub fn consume_java_futex_event(event: &KindlingEventForGo, cca: &Arc<CpuAnalyzer>) {
     let mut ev = Box::new(JavaFutexEvent::default());
-    ev.start_time = event.timestamp;
+    let mut end_time = None;
     for i in 0..event.paramsNumber as usize {
         let user_attributes = event.userAttributes[i];
         match user_attributes.get_key() {
-            Some("end_time") => ev.end_time = read_string_value(user_attributes.get_value()).parse().unwrap(),
+            Some("end_time") => end_time = read_string_value(user_attributes.get_value()).trim().parse::<u64>().ok(),
             Some("data") => ev.data_val = read_string_value(user_attributes.get_value()),
             _ => (),
         }
     }
+    // 开始时间和结束时间来自不同的时钟，分别换算后再组合
+    let clock_config = &cca.config.clock;
+    let end_time = end_time.unwrap_or(event.timestamp);
+    let range = cca.clock.to_realtime_range(event.timestamp, clock_config.java_futex_start, end_time, clock_config.java_futex_end);
+    ev.clock_unreconciled = range.is_none();
+    let (start_time, end_time) = range.unwrap_or_else(|| {
+        cca.counters.clock_unreconciled.fetch_add(1, Ordering::Relaxed);
+        ev.raw_start_time = Some(event.timestamp);
+        ev.raw_end_time = Some(end_time);
+        cca.clock.estimate_range(event.timestamp, clock_config.java_futex_start, end_time, clock_config.java_futex_end)
+    });
+    ev.start_time = start_time;
+    ev.end_time = end_time;
     //println!("{}", ev);
 
-    let mut ca_guard = cca.lock().unwrap();
-    ca_guard.put_event_to_segments(
+    cca.put_event_to_segments(
         event.get_pid(),
         event.get_tid(),
         &event.get_comm(),
+        &event.get_container_id(),
         ev,
     );
 }
 
+impl Default for CpuAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CpuAnalyzer {
     pub fn new() -> Self {
+        Self::with_sink(Box::new(StdoutJsonSink))
+    }
+
+    pub fn with_sink(sink: Box<dyn SegmentSink>) -> Self {
+        Self::with_config(CpuAnalyzerConfig::default(), sink)
+    }
+
+    pub fn with_config(config: CpuAnalyzerConfig, sink: Box<dyn SegmentSink>) -> Self {
         CpuAnalyzer {
-            cpu_pid_events: HashMap::new(),
+            clock: ClockSync::new(config.clock.clone()),
+            global_bucket: config.rate_limit.global_bucket().map(Mutex::new),
+            eviction_lock: Mutex::new(()),
+            config,
+            cpu_pid_events: RwLock::new(HashMap::new()),
+            sink: Mutex::new(sink),
+            latest_event_time: AtomicU64::new(0),
+            counters: AnalyzerCounters::default(),
         }
     }
 
-    pub fn put_event_to_segments(&mut self, pid: u32, tid: u32, thread_name: &str, event: Box<dyn TimedEvent>) {
-        let tid_cpu_events = self.cpu_pid_events.entry(pid).or_insert_with(HashMap::new);
-        let time_segments = tid_cpu_events.entry(tid).or_insert_with(|| {
-            let base_time = event.start_timestamp() / NANO_TO_SECONDS;
-            let segments = create_initial_segments(base_time);
-            TimeSegments {
-                pid,
-                tid,
+    // 配置的偏移无效时退回 UTC
+    pub fn index_timezone(&self) -> FixedOffset {
+        FixedOffset::east_opt(self.config.index_utc_offset).unwrap_or_else(|| FixedOffset::east_opt(0).unwrap())
+    }
+
+    pub fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+
+    pub fn stats(&self) -> AnalyzerStats {
+        self.counters.snapshot()
+    }
+
+    pub fn set_sink(&self, sink: Box<dyn SegmentSink>) {
+        *self.sink.lock().unwrap() = sink;
+    }
+
+    // 早于该时间(ns)结束的 segment 不会再有重排序缓冲中的事件放入
+    pub(crate) fn completed_before(&self) -> u64 {
+        self.latest_event_time.load(Ordering::Relaxed).saturating_sub(self.config.reorder_delay)
+    }
+
+    pub(crate) fn pid_events(&self, pid: u32) -> Option<Arc<Mutex<ThreadSegments>>> {
+        self.cpu_pid_events.read().unwrap().get(&pid).cloned()
+    }
+
+    // 调用方每次只持有一个进程的锁，避免多个进程锁之间的死锁
+    pub(crate) fn all_pid_events(&self) -> Vec<(u32, Arc<Mutex<ThreadSegments>>)> {
+        self.cpu_pid_events.read().unwrap().iter()
+            .map(|(pid, tid_cpu_events)| (*pid, Arc::clone(tid_cpu_events)))
+            .collect()
+    }
+
+    pub(crate) fn pid_events_or_insert(&self, pid: u32) -> Arc<Mutex<ThreadSegments>> {
+        if let Some(tid_cpu_events) = self.pid_events(pid) {
+            return tid_cpu_events;
+        }
+        let mut cpu_pid_events = self.cpu_pid_events.write().unwrap();
+        Arc::clone(cpu_pid_events.entry(pid).or_insert_with(|| Arc::new(Mutex::new(ThreadSegments::default()))))
+    }
+
+    pub fn put_event_to_segments(&self, pid: u32, tid: u32, thread_name: &str, container_id: &str, mut event: Box<dyn TimedEvent>) {
+        let pid_events = self.pid_events_or_insert(pid);
+        let mut tid_cpu_events = pid_events.lock().unwrap();
+        if self.config.rate_limit.is_enabled() {
+            let mut global_bucket = self.global_bucket.as_ref().map(|bucket| bucket.lock().unwrap());
+            match tid_cpu_events.sampler.admit(tid, event.kind(), &self.config.rate_limit, global_bucket.as_deref_mut()) {
+                Some(weight) => event.set_sample_weight(event.sample_weight() * weight),
+                None => {
+                    self.counters.rate_limited.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+        self.latest_event_time.fetch_max(event.start_timestamp().min(self.clock.now()), Ordering::Relaxed);
+        if self.config.reorder_delay == 0 {
+            self.place_event(&mut tid_cpu_events.threads, pid, tid, thread_name, container_id, event);
+        } else {
+            let buffer = tid_cpu_events.pending.entry(tid).or_default();
+            let out_of_order = buffer.push(PendingEvent {
                 thread_name: thread_name.to_string(),
-                base_time,
-                segments,
+                container_id: container_id.to_string(),
+                event,
+            });
+            if out_of_order {
+                self.counters.out_of_order.fetch_add(1, Ordering::Relaxed);
+            }
+            let ready = buffer.drain_ready(buffer.latest_start_time().saturating_sub(self.config.reorder_delay));
+            for pending in ready {
+                self.place_event(&mut tid_cpu_events.threads, pid, tid, &pending.thread_name, &pending.container_id, pending.event);
             }
+        }
+        // 淘汰需要锁住其他进程，先释放当前进程的锁
+        drop(tid_cpu_events);
+        self.enforce_memory_budget();
+    }
+
+    // 将所有线程缓冲中已超过重排序延迟的事件放入 segment，应在每批事件处理完后调用，
+    // 以免不再产生事件的线程一直积压
+    pub fn flush_reorder_buffers(&self) {
+        self.flush_pending_before(self.completed_before());
+    }
+
+    // 不论延迟，放入所有缓冲中的事件，用于退出前或需要立即看到全部数据时
+    pub fn flush_all_pending(&self) {
+        self.flush_pending_before(u64::MAX);
+    }
+
+    fn flush_pending_before(&self, cutoff: u64) {
+        for (pid, pid_events) in self.all_pid_events() {
+            let mut tid_cpu_events = pid_events.lock().unwrap();
+            let ThreadSegments { threads, pending, .. } = &mut *tid_cpu_events;
+            for (tid, buffer) in pending.iter_mut() {
+                for ready in buffer.drain_ready(cutoff) {
+                    self.place_event(threads, pid, *tid, &ready.thread_name, &ready.container_id, ready.event);
+                }
+            }
+            pending.retain(|_, buffer| !buffer.is_empty());
+            if !tid_cpu_events.process_events.is_empty() {
+                let released = tid_cpu_events.release_expired_process_events();
+                self.counters.release_bytes(released);
+            }
+        }
+        self.enforce_memory_budget();
+    }
+
+    fn place_event(&self, threads: &mut HashMap<u32, TimeSegments>, pid: u32, tid: u32, thread_name: &str, container_id: &str, event: Box<dyn TimedEvent>) {
+        let time_segments = threads.entry(tid).or_insert_with(|| {
+            let base_time = event.start_timestamp() / NANO_TO_SECONDS;
+            let segments = create_initial_segments(base_time);
+            TimeSegments::new(pid, tid, thread_name.to_string(), container_id.to_string(), base_time, segments)
         });
 
         if event.end_timestamp() / NANO_TO_SECONDS < time_segments.base_time {
+            self.counters.dropped_too_old.fetch_add(1, Ordering::Relaxed);
             return;
         }
-        let end_offset = (event.end_timestamp() / NANO_TO_SECONDS - time_segments.base_time) as i32;
-
+        let mut end_offset = (event.end_timestamp() / NANO_TO_SECONDS - time_segments.base_time) as i32;
 
-        if event.start_timestamp() / NANO_TO_SECONDS < time_segments.base_time {
-            return;
+        // 开始于窗口之前但仍与窗口重叠的迟到事件放入已有的 segment
+        let start_second = event.start_timestamp() / NANO_TO_SECONDS;
+        if start_second < time_segments.base_time {
+            self.counters.late_placed.fetch_add(1, Ordering::Relaxed);
         }
-        let start_offset = (event.start_timestamp() / NANO_TO_SECONDS - time_segments.base_time) as i32;
+        let mut start_offset = start_second.saturating_sub(time_segments.base_time) as i32;
         let should_clear_segments = start_offset >= MAX_SEGMENT_SIZE as i32 || end_offset > MAX_SEGMENT_SIZE as i32;
 
         if should_clear_segments {
             if start_offset * 2 >= 3 * MAX_SEGMENT_SIZE as i32 {
-                time_segments.segments.clear();
-                time_segments.base_time = event.start_timestamp() / NANO_TO_SECONDS;
-                let end_offset = end_offset - start_offset;
-                let start_offset = 0;
-                time_segments.segments = create_initial_segments(time_segments.base_time);
+                // 跳过的时间超过窗口的一半，整体重置
+                self.counters.release_bytes(time_segments.bytes());
+                let base_time = event.start_timestamp() / NANO_TO_SECONDS;
+                time_segments.base_time = base_time;
+                time_segments.segments.reset(|i| new_segment(base_time, i));
+                end_offset -= start_offset;
+                start_offset = 0;
             } else {
                 let clear_size = MAX_SEGMENT_SIZE / 2;
-                time_segments.base_time += clear_size as u64;
-                let mut start_offset = start_offset - clear_size as i32;
-                let end_offset = end_offset - clear_size as i32;
-                if start_offset < 0 {
-                    start_offset = 0;
+                let released: usize = (0..clear_size)
+                    .filter_map(|i| time_segments.segments.get_by_index(i))
+                    .map(|segment| segment.bytes())
+                    .sum();
+                self.counters.release_bytes(released);
+                let base_time = time_segments.base_time + clear_size as u64;
+                time_segments.base_time = base_time;
+                time_segments.segments.advance(clear_size, |i| new_segment(base_time, i));
+                start_offset = (start_offset - clear_size as i32).max(0);
+                end_offset -= clear_size as i32;
+            }
+        }
+        time_segments.update_thread_name(thread_name, event.start_timestamp());
+        // 跨多个 segment 的事件共享同一份数据
+        let event: Arc<dyn TimedEvent> = Arc::from(event);
+        if time_segments.container_id.is_empty() && !container_id.is_empty() {
+            time_segments.container_id = container_id.to_string();
+        }
+        // 事件的内存计入最后一个 segment，窗口前移时最后才被丢弃
+        let last_offset = end_offset.min(MAX_SEGMENT_SIZE as i32 - 1);
+        let mut added = 0;
+        for i in start_offset..=last_offset {
+            if let Some(segment) = time_segments.segments.get_by_index_mut(i as usize) {
+                let bytes_before = segment.bytes();
+                if i == last_offset {
+                    Self::handle_event(&event, segment);
+                } else {
+                    segment.put_shared_event(event.clone());
                 }
-                for i in 0..clear_size {
-                    let moved_index = i + clear_size;
-                    if let Some(segment) = time_segments.segments.get_by_index(moved_index) {
-                        let mut cloned_segment = segment.clone();
-                        Self::handle_event(&event, &mut cloned_segment);
-                        cloned_segment.is_send = 0;
-                        time_segments.segments.update_by_index(i, cloned_segment);
+                added += segment.bytes() - bytes_before;
+            }
+        }
+        self.counters.bytes_held.fetch_add(added as u64, Ordering::Relaxed);
+    }
+
+    // 放入进程级别的事件(如 cgroup 限流)，加入该进程每个线程与之重叠的 segment，不创建新线程
+    pub fn put_process_event(&self, pid: u32, event: Arc<dyn TimedEvent>) {
+        let pid_events = match self.pid_events(pid) {
+            Some(pid_events) => pid_events,
+            None => return,
+        };
+        let mut tid_cpu_events = pid_events.lock().unwrap();
+        let mut added = 0;
+        for time_segments in tid_cpu_events.threads.values_mut() {
+            for i in 0..MAX_SEGMENT_SIZE {
+                if let Some(segment) = time_segments.segments.get_by_index_mut(i) {
+                    if segment.end_time() <= event.start_timestamp() || segment.start_time() >= event.end_timestamp() {
+                        continue;
                     }
-                    let segment_tmp = Segment::new(
-                        (time_segments.base_time + (moved_index as u64)) * NANO_TO_SECONDS,
-                        (time_segments.base_time + ((moved_index + 1) as u64)) * NANO_TO_SECONDS,
-                    );
-                    time_segments.segments.update_by_index(moved_index, segment_tmp);
+                    let bytes_before = segment.bytes();
+                    segment.put_shared_event(event.clone());
+                    added += segment.bytes() - bytes_before;
                 }
             }
         }
-        time_segments.update_thread_name(&thread_name);
-        for i in start_offset..=end_offset.min(MAX_SEGMENT_SIZE as i32 - 1) {
-            if let Some(segment) = time_segments.segments.get_by_index(i as usize) {
-                let mut cloned_segment = segment.clone();
-                Self::handle_event(&event, &mut cloned_segment);
-                cloned_segment.is_send = 0;
-                time_segments.segments.update_by_index(i as usize, cloned_segment);
-            }
+        if added > 0 {
+            added += event.size_bytes();
+            tid_cpu_events.process_events.push(event);
         }
+        self.counters.bytes_held.fetch_add(added as u64, Ordering::Relaxed);
+        let released = tid_cpu_events.release_expired_process_events();
+        self.counters.release_bytes(released);
+        drop(tid_cpu_events);
+        self.enforce_memory_budget();
     }
 
-    pub fn handle_event(event: &Box<dyn TimedEvent>, segment: &mut Segment) {
-        if event.kind() == 0 {
-            let cpu_ev = event.as_any().downcast_ref::<CpuEvent>();
-            segment.put_cpu_event(cpu_ev.unwrap().clone());
-        } else if event.kind() == 1 {
-            let java_futex_ev = event.as_any().downcast_ref::<JavaFutexEvent>();
-            segment.put_java_futex_event(java_futex_ev.unwrap().clone());
+    pub fn handle_event(event: &Arc<dyn TimedEvent>, segment: &mut Segment) {
+        segment.put_event(event.clone());
+    }
+
+    // 超出内存预算时，按最近查询时间、再按数据新旧依次淘汰线程，直到低于预算的 90%
+    pub fn enforce_memory_budget(&self) {
+        let budget = self.config.memory_budget as u64;
+        if budget == 0 || self.counters.bytes_held.load(Ordering::Relaxed) <= budget {
+            return;
+        }
+        // 已有其他线程在淘汰时直接返回
+        let _eviction = match self.eviction_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        let target = budget - budget / 10;
+        let mut candidates = Vec::new();
+        for (pid, pid_events) in self.all_pid_events() {
+            let tid_cpu_events = pid_events.lock().unwrap();
+            for time_segments in tid_cpu_events.threads.values() {
+                candidates.push((time_segments.last_queried(), time_segments.base_time, pid, time_segments.tid));
+            }
+        }
+        candidates.sort_unstable();
+        for (_, _, pid, tid) in candidates {
+            if self.counters.bytes_held.load(Ordering::Relaxed) <= target {
+                break;
+            }
+            let pid_events = match self.pid_events(pid) {
+                Some(pid_events) => pid_events,
+                None => continue,
+            };
+            let mut tid_cpu_events = pid_events.lock().unwrap();
+            tid_cpu_events.sampler.forget_thread(tid);
+            if let Some(time_segments) = tid_cpu_events.threads.remove(&tid) {
+                let bytes = time_segments.bytes() + tid_cpu_events.release_expired_process_events();
+                self.counters.release_bytes(bytes);
+                self.counters.bytes_evicted.fetch_add(bytes as u64, Ordering::Relaxed);
+                self.counters.threads_evicted.fetch_add(1, Ordering::Relaxed);
+            }
         }
     }
 
@@ -179,35 +458,82 @@ impl CpuAnalyzer {
         //println!("{:?}", self.cpu_pid_events);
     }
 
-    pub fn send_events(&mut self, pid: u32, start_time: u64, end_time: u64) {
-        let tid_cpu_events = match self.cpu_pid_events.get_mut(&pid) {
-            Some(tid_cpu_events) => tid_cpu_events,
+    pub fn send_events(&self, pid: u32, start_time: u64, end_time: u64) {
+        self.send_events_with_options(pid, start_time, end_time, &ExportOptions::default());
+    }
+
+    // 窗口重叠的多次触发只发送尚未发送过的事件，force_resend 时重新发送窗口内的全部事件
+    pub fn send_events_with_options(&self, pid: u32, start_time: u64, end_time: u64, options: &ExportOptions) {
+        let pid_events = match self.pid_events(pid) {
+            Some(pid_events) => pid_events,
             None => {
                 return;
             }
         };
+        let mut tid_cpu_events = pid_events.lock().unwrap();
 
         let start_time_second = start_time / NANO_TO_SECONDS;
         let end_time_second = end_time / NANO_TO_SECONDS;
 
-        for time_segments in tid_cpu_events.values_mut() {
+        let index_timezone = self.index_timezone();
+        let mut records = Vec::new();
+        for time_segments in tid_cpu_events.threads.values_mut() {
             if end_time_second < time_segments.base_time || start_time_second > time_segments.base_time + (MAX_SEGMENT_SIZE as u64) {
                 continue;
             }
 
-            let start_index = (start_time_second - time_segments.base_time) as i32;
-            let end_index = (end_time_second - time_segments.base_time).min(MAX_SEGMENT_SIZE as u64) as i32;
+            time_segments.touch();
+            time_segments.prune_sent_events();
+            if options.force_resend {
+                time_segments.reset_sent_between(start_time_second * NANO_TO_SECONDS, (end_time_second + 1).saturating_mul(NANO_TO_SECONDS), &options.kinds);
+            }
+            let start_index = start_time_second.saturating_sub(time_segments.base_time) as usize;
+            let end_index = (end_time_second - time_segments.base_time).min(MAX_SEGMENT_SIZE as u64) as usize;
 
+            // 跨多个 segment 的事件只随第一个导出它的 segment 发送
             for i in start_index..=end_index {
-                if let Some(segment) = time_segments.segments.get_by_index_mut(i as usize) {
-                    if segment.is_not_empty() {
-                        segment.update_index_timestamp();
-                        println!("{:?}", segment);
-                        segment.is_send = 1;
-                    }
+                let events = match time_segments.segments.get_by_index(i) {
+                    Some(segment) => time_segments.unsent_events_of(segment, &options.kinds),
+                    None => continue,
+                };
+                if events.is_empty() {
+                    continue;
+                }
+                time_segments.mark_sent(&events);
+                if let Some(segment) = time_segments.segments.get_by_index_mut(i) {
+                    segment.update_index_time();
                 }
+                let segment = time_segments.segments.get_by_index(i).unwrap();
+                records.push(SegmentRecord::with_events(time_segments, segment, &events, &index_timezone));
             }
         }
+        drop(tid_cpu_events);
+
+        let mut sink = self.sink.lock().unwrap();
+        for record in records.iter() {
+            if let Err(err) = sink.send(record) {
+                eprintln!("failed to send segment of pid {} tid {}: {}", record.pid, record.tid, err);
+            }
+        }
+        if let Err(err) = sink.flush() {
+            eprintln!("failed to flush segment sink: {}", err);
+        }
+    }
+
+    // 异常与 segment 输出到同一个 sink
+    pub fn send_anomalies(&self, anomalies: &[Anomaly]) {
+        if anomalies.is_empty() {
+            return;
+        }
+        let mut sink = self.sink.lock().unwrap();
+        for anomaly in anomalies {
+            if let Err(err) = sink.send_anomaly(anomaly) {
+                eprintln!("failed to send anomaly of pid {} tid {}: {}", anomaly.pid, anomaly.tid, err);
+            }
+        }
+        if let Err(err) = sink.flush() {
+            eprintln!("failed to flush segment sink: {}", err);
+        }
     }
 }
 
@@ -231,12 +557,261 @@ fn read_string_value(val: Option<&[u8]>) -> String {
 
 fn create_initial_segments(base_time: u64) -> CircleQueue {
     let mut segments = CircleQueue::new(MAX_SEGMENT_SIZE);
-    for i in 0..MAX_SEGMENT_SIZE {
-        let segment = Segment::new(
-            (base_time + (i as u64)) * NANO_TO_SECONDS,
-            (base_time + (i as u64) + 1) * NANO_TO_SECONDS,
-        );
-        segments.update_by_index(i, segment);
-    }
+    segments.reset(|i| new_segment(base_time, i));
     segments
-}
\ No newline at end of file
+}
+
+fn new_segment(base_time: u64, index: usize) -> Segment {
+    Segment::new(
+        (base_time + (index as u64)) * NANO_TO_SECONDS,
+        (base_time + (index as u64) + 1) * NANO_TO_SECONDS,
+    )
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cgroup::CgroupThrottleEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+    use crate::cpuAnalyzer::time_event::{CPU_EVENT_KIND, JAVA_FUTEX_EVENT_KIND};
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+
+    fn cpu_event(start_time: u64, end_time: u64) -> Box<CpuEvent> {
+        Box::new(CpuEvent {
+            start_time,
+            end_time,
+            type_specs: vec![end_time - start_time],
+            time_type: vec![1],
+            ..Default::default()
+        })
+    }
+
+    fn analyzer(config: CpuAnalyzerConfig) -> (CpuAnalyzer, MemorySink) {
+        let sink = MemorySink::new();
+        (CpuAnalyzer::with_config(config, Box::new(sink.clone())), sink)
+    }
+
+    fn sent_events(sink: &MemorySink) -> Vec<(u32, u64, u64)> {
+        sink.take().iter()
+            .flat_map(|record| record.cpu_events.iter().map(move |event| (record.tid, event.start_time, event.end_time)))
+            .collect()
+    }
+
+    #[test]
+    fn spanning_event_is_sent_once_across_overlapping_windows() {
+        let (cca, sink) = analyzer(CpuAnalyzerConfig::default());
+        let spanning = (BASE * NANO_TO_SECONDS, (BASE + 3) * NANO_TO_SECONDS);
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(spanning.0, spanning.1));
+
+        cca.send_events(PID, BASE * NANO_TO_SECONDS, (BASE + 1) * NANO_TO_SECONDS);
+        assert_eq!(sent_events(&sink), vec![(10, spanning.0, spanning.1)]);
+
+        let later = ((BASE + 2) * NANO_TO_SECONDS + 100, (BASE + 2) * NANO_TO_SECONDS + 200);
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(later.0, later.1));
+        cca.send_events(PID, BASE * NANO_TO_SECONDS, (BASE + 3) * NANO_TO_SECONDS);
+        assert_eq!(sent_events(&sink), vec![(10, later.0, later.1)]);
+
+        cca.send_events(PID, (BASE + 1) * NANO_TO_SECONDS, (BASE + 3) * NANO_TO_SECONDS);
+        assert!(sent_events(&sink).is_empty());
+
+        // 强制重发时窗口内的事件各发送一次
+        let options = ExportOptions { force_resend: true, kinds: Vec::new() };
+        cca.send_events_with_options(PID, (BASE + 1) * NANO_TO_SECONDS, (BASE + 3) * NANO_TO_SECONDS, &options);
+        let mut resent = sent_events(&sink);
+        resent.sort();
+        assert_eq!(resent, vec![(10, spanning.0, spanning.1), (10, later.0, later.1)]);
+    }
+
+    fn held_by_threads(cca: &CpuAnalyzer) -> u64 {
+        let pid_events = cca.pid_events(PID).unwrap();
+        let tid_cpu_events = pid_events.lock().unwrap();
+        tid_cpu_events.threads.values().map(|time_segments| time_segments.bytes() as u64).sum()
+    }
+
+    fn tids(cca: &CpuAnalyzer) -> Vec<u32> {
+        let pid_events = cca.pid_events(PID).unwrap();
+        let mut tids: Vec<u32> = pid_events.lock().unwrap().threads.keys().copied().collect();
+        tids.sort();
+        tids
+    }
+
+    fn event_bytes() -> u64 {
+        let (cca, _) = analyzer(CpuAnalyzerConfig::default());
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(BASE * NANO_TO_SECONDS, BASE * NANO_TO_SECONDS + 100));
+        cca.stats().bytes_held
+    }
+
+    #[test]
+    fn memory_budget_evicts_least_recently_queried_threads() {
+        let bytes = event_bytes();
+        let (cca, _) = analyzer(CpuAnalyzerConfig { memory_budget: (bytes * 5 / 2) as usize, ..Default::default() });
+        for (tid, second) in [(10, BASE), (11, BASE + 1)] {
+            cca.put_event_to_segments(PID, tid, "worker", "", cpu_event(second * NANO_TO_SECONDS, second * NANO_TO_SECONDS + 100));
+        }
+        // 只查询 tid 10，未被查询过的线程中数据较旧的 tid 11 先被淘汰
+        cca.send_events(PID, BASE * NANO_TO_SECONDS, BASE * NANO_TO_SECONDS + 100);
+        cca.put_event_to_segments(PID, 12, "worker", "", cpu_event((BASE + 2) * NANO_TO_SECONDS, (BASE + 2) * NANO_TO_SECONDS + 100));
+
+        assert_eq!(tids(&cca), vec![10, 12]);
+        let stats = cca.stats();
+        assert_eq!(stats.threads_evicted, 1);
+        assert_eq!(stats.bytes_evicted, bytes);
+        assert_eq!(stats.bytes_held, 2 * bytes);
+        assert_eq!(stats.bytes_held, held_by_threads(&cca));
+    }
+
+    #[test]
+    fn spanning_event_is_charged_once() {
+        let (cca, _) = analyzer(CpuAnalyzerConfig::default());
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(BASE * NANO_TO_SECONDS, (BASE + 3) * NANO_TO_SECONDS));
+        assert_eq!(cca.stats().bytes_held, held_by_threads(&cca));
+        // 窗口整体重置后释放全部内存
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event((BASE + 100) * NANO_TO_SECONDS, (BASE + 100) * NANO_TO_SECONDS + 100));
+        assert_eq!(cca.stats().bytes_held, event_bytes());
+        assert_eq!(cca.stats().bytes_held, held_by_threads(&cca));
+    }
+
+    #[test]
+    fn process_event_is_charged_once_and_released() {
+        let (cca, _) = analyzer(CpuAnalyzerConfig::default());
+        for tid in [10, 11] {
+            cca.put_event_to_segments(PID, tid, "worker", "", cpu_event(BASE * NANO_TO_SECONDS, BASE * NANO_TO_SECONDS + 100));
+        }
+        let held_before = cca.stats().bytes_held;
+        let event: Arc<dyn TimedEvent> = Arc::new(CgroupThrottleEvent {
+            start_time: BASE * NANO_TO_SECONDS,
+            end_time: (BASE + 2) * NANO_TO_SECONDS,
+            cgroup: "/kubepods/pod1".to_string(),
+            ..Default::default()
+        });
+        let size = event.size_bytes() as u64;
+        cca.put_process_event(PID, event);
+        // 两个线程各 2 个 segment 只保存引用，数据本身由进程持有
+        let refs = held_by_threads(&cca) - held_before;
+        assert_eq!(cca.stats().bytes_held, held_before + refs + size);
+        assert!(refs < size);
+
+        // 两个线程的窗口都移过该事件后释放
+        for tid in [10, 11] {
+            cca.put_event_to_segments(PID, tid, "worker", "", cpu_event((BASE + 100) * NANO_TO_SECONDS, (BASE + 100) * NANO_TO_SECONDS + 100));
+        }
+        cca.flush_reorder_buffers();
+        assert!(cca.pid_events(PID).unwrap().lock().unwrap().process_events.is_empty());
+        assert_eq!(cca.stats().bytes_held, held_by_threads(&cca));
+
+        // 不存在的进程不会创建数据
+        cca.put_process_event(PID + 1, Arc::new(CgroupThrottleEvent::default()));
+        assert!(cca.pid_events(PID + 1).is_none());
+    }
+
+    fn pending_count(cca: &CpuAnalyzer, pid: u32) -> usize {
+        let pid_events = cca.pid_events(pid).unwrap();
+        let tid_cpu_events = pid_events.lock().unwrap();
+        tid_cpu_events.pending.values().map(|buffer| buffer.len()).sum()
+    }
+
+    #[test]
+    fn out_of_order_events_are_reordered_within_delay() {
+        let (cca, sink) = analyzer(CpuAnalyzerConfig { reorder_delay: NANO_TO_SECONDS, ..Default::default() });
+        let t = BASE * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(t + 500, t + 600));
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(t + 100, t + 200));
+        assert_eq!(cca.stats().out_of_order, 1);
+        assert_eq!(pending_count(&cca, PID), 2);
+
+        // 本线程的水位超过延迟后放入 segment，按开始时间排序
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(t + 2 * NANO_TO_SECONDS, t + 2 * NANO_TO_SECONDS + 100));
+        assert_eq!(pending_count(&cca, PID), 1);
+        cca.send_events(PID, t, t);
+        assert_eq!(sent_events(&sink), vec![(10, t + 100, t + 200), (10, t + 500, t + 600)]);
+    }
+
+    #[test]
+    fn skewed_process_does_not_drain_other_buffers() {
+        let (cca, _) = analyzer(CpuAnalyzerConfig { reorder_delay: NANO_TO_SECONDS, ..Default::default() });
+        let now = cca.clock().now();
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(now - 100, now));
+        // 另一个进程的时钟超前 30s
+        let ahead = now + 30 * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID + 1, 20, "worker", "", cpu_event(ahead, ahead + 100));
+        cca.flush_reorder_buffers();
+        assert_eq!(pending_count(&cca, PID), 1);
+
+        cca.flush_all_pending();
+        assert_eq!(pending_count(&cca, PID), 0);
+        assert_eq!(pending_count(&cca, PID + 1), 0);
+    }
+
+    #[test]
+    fn late_events_are_placed_or_dropped() {
+        let (cca, sink) = analyzer(CpuAnalyzerConfig::default());
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(BASE * NANO_TO_SECONDS, BASE * NANO_TO_SECONDS + 100));
+        // 窗口整体前移到 BASE + 70
+        let base = (BASE + 70) * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(base, base + 100));
+
+        // 开始于窗口之前、结束于窗口内的事件放入第一个 segment
+        let late = (base - NANO_TO_SECONDS / 2, base + NANO_TO_SECONDS / 2);
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(late.0, late.1));
+        cca.put_event_to_segments(PID, 10, "worker", "", cpu_event(base - 2 * NANO_TO_SECONDS, base - NANO_TO_SECONDS));
+        let stats = cca.stats();
+        assert_eq!(stats.late_placed, 1);
+        assert_eq!(stats.dropped_too_old, 1);
+
+        cca.send_events(PID, base, base);
+        let mut sent = sent_events(&sink);
+        sent.sort();
+        assert_eq!(sent, vec![(10, late.0, late.1), (10, base, base + 100)]);
+    }
+
+    fn futex_event(start_time: u64, end_time: u64) -> Box<JavaFutexEvent> {
+        Box::new(JavaFutexEvent {
+            start_time,
+            end_time,
+            data_val: "lock=A".to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn kinds_options(kinds: &[&str]) -> ExportOptions {
+        ExportOptions { force_resend: false, kinds: kinds.iter().map(|kind| kind.to_string()).collect() }
+    }
+
+    #[test]
+    fn futex_only_segment_is_exported() {
+        let (cca, sink) = analyzer(CpuAnalyzerConfig::default());
+        let start_time = BASE * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID, 10, "worker", "", futex_event(start_time, start_time + 100));
+        cca.send_events(PID, start_time, start_time);
+        let records = sink.take();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].cpu_events.is_empty());
+        assert_eq!(records[0].java_futex_events.len(), 1);
+        assert_eq!(records[0].java_futex_events[0].start_time, start_time);
+    }
+
+    #[test]
+    fn export_is_filtered_by_kind() {
+        let (cca, sink) = analyzer(CpuAnalyzerConfig::default());
+        let start_time = BASE * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID, 10, "worker", "", futex_event(start_time, start_time + 100));
+        cca.put_event_to_segments(PID, 11, "worker", "", cpu_event(start_time, start_time + 100));
+
+        // 只有 cpu 事件的线程不导出
+        cca.send_events_with_options(PID, start_time, start_time, &kinds_options(&[JAVA_FUTEX_EVENT_KIND]));
+        let records = sink.take();
+        assert_eq!(records.iter().map(|record| record.tid).collect::<Vec<_>>(), vec![10]);
+        assert_eq!(records[0].java_futex_events.len(), 1);
+
+        // 按类型过滤时其他类型的事件仍未发送
+        cca.send_events_with_options(PID, start_time, start_time, &kinds_options(&[CPU_EVENT_KIND, JAVA_FUTEX_EVENT_KIND]));
+        let records = sink.take();
+        assert_eq!(records.iter().map(|record| record.tid).collect::<Vec<_>>(), vec![11]);
+        assert!(records[0].java_futex_events.is_empty());
+        assert_eq!(records[0].cpu_events.len(), 1);
+
+        cca.send_events(PID, start_time, start_time);
+        assert!(sink.take().is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::interval_info::BlockTarget;
+use crate::cpuAnalyzer::model::{CpuInterval, CpuTimeType, TimeSegments};
+
+#[derive(Debug, Clone)]
+pub struct CriticalPathConfig {
+    // 沿唤醒链向上追溯的最大层数
+    pub max_depth: usize,
+}
+
+impl Default for CriticalPathConfig {
+    fn default() -> Self {
+        CriticalPathConfig {
+            max_depth: 8,
+        }
+    }
+}
+
+// 阻塞区间唤醒者的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WakerSource {
+    // 关联上的 java 锁持有者
+    JavaLockOwner,
+    // off_info 中的 waker_tid 字段
+    WakerTid,
+    // 推断：同进程另一线程等待同一把锁的 futex 区间与之重叠并先结束，
+    // 该线程先拿到锁，释放时唤醒了本线程
+    FutexHandoff,
+    // 没有找到唤醒者，该段时间只能算作本线程的阻塞
+    NotFound,
+}
+
+// 关键路径上的一段时间，归属于某个线程
+#[derive(Debug, Clone, Serialize)]
+pub struct CriticalPathStep {
+    pub tid: u32,
+    pub thread_name: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub time_type: CpuTimeType,
+    pub description: String,
+    // 0 为被分析的线程，每经过一次唤醒加 1
+    pub depth: usize,
+    // 该段时间替哪个线程推进了进度(被唤醒的线程)
+    pub wakee_tid: Option<u32>,
+    // 阻塞区间的唤醒者及其来源，on cpu 区间为 None。唤醒者超出深度、成环或没有数据时
+    // 仍记录其 tid，但不展开
+    pub waker_tid: Option<u32>,
+    pub waker_source: Option<WakerSource>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ThreadAttribution {
+    pub tid: u32,
+    pub thread_name: String,
+    pub on_cpu_time: u64,
+    pub off_cpu_time: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CriticalPath {
+    pub pid: u32,
+    pub tid: u32,
+    pub start_time: u64,
+    pub end_time: u64,
+    // 按时间排序
+    pub steps: Vec<CriticalPathStep>,
+    // 按归属时长降序
+    pub threads: Vec<ThreadAttribution>,
+    // 路径上找不到唤醒者的阻塞时长，占比高时说明结果主要停留在被分析的线程本身
+    pub waker_not_found_time: u64,
+}
+
+impl CriticalPath {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn lock_address(interval: &CpuInterval) -> Option<&str> {
+    match interval.info.as_ref()?.target.as_ref()? {
+        BlockTarget::Lock { address } => Some(address),
+        _ => None,
+    }
+}
+
+// 两段 futex 等待的是否可能是同一把锁，任一方没有锁地址时不排除
+fn same_lock(interval: &CpuInterval, other: &CpuInterval) -> bool {
+    match (lock_address(interval), lock_address(other)) {
+        (Some(address), Some(other_address)) => address == other_address,
+        _ => true,
+    }
+}
+
+struct PathWalker<'a> {
+    threads: &'a HashMap<u32, TimeSegments>,
+    // 查询范围内各线程的 futex 区间，用于推断没有显式唤醒者的 futex 等待
+    futex_waits: Vec<(u32, CpuInterval)>,
+    max_depth: usize,
+    steps: Vec<CriticalPathStep>,
+    // 当前递归链上的线程，避免互相唤醒时死循环
+    visiting: Vec<u32>,
+}
+
+impl<'a> PathWalker<'a> {
+    // 阻塞区间的唤醒者：依次使用关联上的 java 锁持有者、探针给出的 waker_tid 字段，
+    // 最后对 futex 等待按 FutexHandoff 推断
+    fn waker_of(&self, tid: u32, interval: &CpuInterval) -> Option<(Option<u32>, WakerSource)> {
+        if interval.time_type.is_on_cpu() {
+            return None;
+        }
+        if let Some(owner_tid) = interval.java_lock.as_ref().and_then(|lock| lock.owner_tid) {
+            return Some((Some(owner_tid), WakerSource::JavaLockOwner));
+        }
+        if let Some(waker_tid) = interval.info.as_ref().and_then(|info| info.waker_tid()) {
+            return Some((Some(waker_tid), WakerSource::WakerTid));
+        }
+        if interval.time_type == CpuTimeType::Futex {
+            let handoff = self.futex_waits.iter()
+                .filter(|(other_tid, other)| *other_tid != tid
+                    && other.start_time < interval.end_time
+                    && other.end_time > interval.start_time
+                    && other.end_time < interval.end_time
+                    && same_lock(interval, other))
+                .max_by_key(|(_, other)| other.end_time);
+            if let Some((waker_tid, _)) = handoff {
+                return Some((Some(*waker_tid), WakerSource::FutexHandoff));
+            }
+        }
+        Some((None, WakerSource::NotFound))
+    }
+
+    fn thread_name(&self, tid: u32, timestamp: u64) -> String {
+        self.threads.get(&tid)
+            .map(|time_segments| time_segments.thread_name_at(timestamp).to_string())
+            .unwrap_or_default()
+    }
+
+    fn push_step(&mut self, tid: u32, interval: &CpuInterval, start_time: u64, end_time: u64, depth: usize, wakee_tid: Option<u32>) {
+        if end_time <= start_time {
+            return;
+        }
+        let waker = self.waker_of(tid, interval);
+        self.steps.push(CriticalPathStep {
+            tid,
+            thread_name: self.thread_name(tid, start_time),
+            start_time,
+            end_time,
+            time_type: interval.time_type,
+            description: interval.to_string(),
+            depth,
+            wakee_tid,
+            waker_tid: waker.and_then(|(waker_tid, _)| waker_tid),
+            waker_source: waker.map(|(_, source)| source),
+        });
+    }
+
+    fn walk(&mut self, tid: u32, start_time: u64, end_time: u64, depth: usize, wakee_tid: Option<u32>) {
+        let timeline = match self.threads.get(&tid) {
+            Some(time_segments) => time_segments.timeline_between(start_time, end_time),
+            None => return,
+        };
+        self.visiting.push(tid);
+        for interval in timeline.iter() {
+            let start = interval.start_time.max(start_time);
+            let end = interval.end_time.min(end_time);
+            let waker = self.waker_of(tid, interval).and_then(|(waker_tid, _)| waker_tid)
+                .filter(|waker| depth < self.max_depth && !self.visiting.contains(waker) && self.threads.contains_key(waker));
+            match waker {
+                Some(waker) => {
+                    // 阻塞期间的时间归属到唤醒者，唤醒者没有数据覆盖的部分仍算作本线程的阻塞
+                    let covered_from = self.steps.len();
+                    self.walk(waker, start, end, depth + 1, Some(tid));
+                    let mut covered: Vec<(u64, u64)> = self.steps[covered_from..].iter()
+                        .map(|step| (step.start_time, step.end_time))
+                        .collect();
+                    covered.sort();
+                    let mut cursor = start;
+                    for (covered_start, covered_end) in covered {
+                        if covered_start > cursor {
+                            self.push_step(tid, interval, cursor, covered_start, depth, wakee_tid);
+                        }
+                        cursor = cursor.max(covered_end);
+                    }
+                    self.push_step(tid, interval, cursor, end, depth, wakee_tid);
+                }
+                None => self.push_step(tid, interval, start, end, depth, wakee_tid),
+            }
+        }
+        self.visiting.pop();
+    }
+}
+
+impl CpuAnalyzer {
+    // 从 tid 在 [start_time, end_time) 内的时间线出发，对每段能确定唤醒者的阻塞区间
+    // 递归展开唤醒者在同一时间段内的时间线，得到每段时间真正在推进的线程
+    pub fn critical_path(&self, pid: u32, tid: u32, start_time: u64, end_time: u64, config: &CriticalPathConfig) -> Option<CriticalPath> {
+        let pid_events = self.pid_events(pid)?;
+        let tid_cpu_events = pid_events.lock().unwrap();
+        if !tid_cpu_events.threads.contains_key(&tid) {
+            return None;
+        }
+        let futex_waits = tid_cpu_events.threads.iter()
+            .flat_map(|(other_tid, time_segments)| time_segments.timeline_between(start_time, end_time).into_iter()
+                .filter(|interval| interval.time_type == CpuTimeType::Futex)
+                .map(move |interval| (*other_tid, interval)))
+            .collect();
+        let mut walker = PathWalker {
+            threads: &tid_cpu_events.threads,
+            futex_waits,
+            max_depth: config.max_depth,
+            steps: Vec::new(),
+            visiting: Vec::new(),
+        };
+        walker.walk(tid, start_time, end_time, 0, None);
+        let mut steps = walker.steps;
+        steps.sort_by_key(|step| (step.start_time, step.depth));
+
+        let mut attributions: BTreeMap<u32, ThreadAttribution> = BTreeMap::new();
+        for step in steps.iter() {
+            let attribution = attributions.entry(step.tid).or_insert_with(|| ThreadAttribution {
+                tid: step.tid,
+                thread_name: step.thread_name.clone(),
+                ..Default::default()
+            });
+            if step.time_type.is_on_cpu() {
+                attribution.on_cpu_time += step.end_time - step.start_time;
+            } else {
+                attribution.off_cpu_time += step.end_time - step.start_time;
+            }
+        }
+        let waker_not_found_time = steps.iter()
+            .filter(|step| step.waker_source == Some(WakerSource::NotFound))
+            .map(|step| step.end_time - step.start_time)
+            .sum();
+        let mut threads: Vec<ThreadAttribution> = attributions.into_values().collect();
+        threads.sort_by_key(|thread| Reverse(thread.on_cpu_time + thread.off_cpu_time));
+
+        Some(CriticalPath {
+            pid,
+            tid,
+            start_time,
+            end_time,
+            steps,
+            threads,
+            waker_not_found_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cpu_analyzer::NANO_TO_SECONDS;
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+    const ON: u8 = 0;
+    const FILE: u8 = 1;
+    const FUTEX: u8 = 3;
+
+    // 相对 BASE 的毫秒
+    fn ms(val: u64) -> u64 {
+        BASE * NANO_TO_SECONDS + val * 1_000_000
+    }
+
+    // 以 (类型, 开始毫秒, 结束毫秒) 描述一个线程连续的区间，off_info 按顺序对应 off cpu 区间
+    fn put_thread(cca: &CpuAnalyzer, tid: u32, intervals: &[(u8, u64, u64)], off_info: &str) {
+        cca.put_event_to_segments(PID, tid, &format!("thread-{}", tid), "", Box::new(CpuEvent {
+            start_time: ms(intervals[0].1),
+            end_time: ms(intervals[intervals.len() - 1].2),
+            type_specs: intervals.iter().map(|(_, start, end)| ms(*end) - ms(*start)).collect(),
+            time_type: intervals.iter().map(|(time_type, _, _)| *time_type).collect(),
+            off_info: off_info.to_string(),
+            ..Default::default()
+        }));
+    }
+
+    fn path(cca: &CpuAnalyzer, tid: u32, max_depth: usize) -> CriticalPath {
+        cca.critical_path(PID, tid, ms(0), ms(1000), &CriticalPathConfig { max_depth }).unwrap()
+    }
+
+    fn spans(path: &CriticalPath) -> Vec<(u32, u64, u64, usize)> {
+        path.steps.iter()
+            .map(|step| (step.tid, (step.start_time - ms(0)) / 1_000_000, (step.end_time - ms(0)) / 1_000_000, step.depth))
+            .collect()
+    }
+
+    #[test]
+    fn waker_coverage_splits_blocked_interval() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 1, &[(ON, 0, 100), (FUTEX, 100, 400), (ON, 400, 500)], "futex;waker_tid=2");
+        put_thread(&cca, 2, &[(ON, 150, 300), (FILE, 300, 450)], "read;file=/data/a");
+
+        let path = path(&cca, 1, 8);
+        // 唤醒者没有数据覆盖的 [100, 150) 仍归属本线程
+        assert_eq!(spans(&path), vec![(1, 0, 100, 0), (1, 100, 150, 0), (2, 150, 300, 1), (2, 300, 400, 1), (1, 400, 500, 0)]);
+        let blocked = &path.steps[1];
+        assert_eq!(blocked.waker_tid, Some(2));
+        assert_eq!(blocked.waker_source, Some(WakerSource::WakerTid));
+        assert_eq!(path.steps[2].wakee_tid, Some(1));
+        assert_eq!(path.threads[0].tid, 1);
+        assert_eq!(path.threads[0].on_cpu_time, ms(200) - ms(0));
+        assert_eq!(path.threads[1].off_cpu_time, ms(100) - ms(0));
+        assert_eq!(path.waker_not_found_time, ms(100) - ms(0));
+    }
+
+    #[test]
+    fn mutual_wakers_do_not_loop() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 1, &[(FUTEX, 0, 200)], "futex;waker_tid=2");
+        put_thread(&cca, 2, &[(FUTEX, 0, 200)], "futex;waker_tid=1");
+
+        let path = path(&cca, 1, 8);
+        assert_eq!(spans(&path), vec![(2, 0, 200, 1)]);
+        // 成环的唤醒者仍记录下来，只是不展开
+        assert_eq!(path.steps[0].waker_tid, Some(1));
+    }
+
+    #[test]
+    fn max_depth_stops_expansion() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 1, &[(FUTEX, 0, 200)], "futex;waker_tid=2");
+        put_thread(&cca, 2, &[(FUTEX, 0, 200)], "futex;waker_tid=3");
+        put_thread(&cca, 3, &[(ON, 0, 200)], "");
+
+        assert_eq!(spans(&path(&cca, 1, 1)), vec![(2, 0, 200, 1)]);
+        assert_eq!(spans(&path(&cca, 1, 2)), vec![(3, 0, 200, 2)]);
+    }
+
+    #[test]
+    fn futex_handoff_is_inferred() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 1, &[(ON, 0, 100), (FUTEX, 100, 400)], "futex;uaddr=0x10");
+        put_thread(&cca, 2, &[(FUTEX, 50, 200), (ON, 200, 500)], "futex;uaddr=0x10");
+        // 等待另一把锁的线程不参与推断
+        put_thread(&cca, 3, &[(FUTEX, 50, 300), (ON, 300, 500)], "futex;uaddr=0x20");
+
+        let path = path(&cca, 1, 8);
+        let blocked = path.steps.iter().find(|step| step.tid == 1 && step.time_type == CpuTimeType::Futex);
+        assert!(blocked.is_none());
+        assert_eq!(spans(&path), vec![(1, 0, 100, 0), (2, 100, 200, 1), (2, 200, 400, 1)]);
+        assert_eq!(path.steps[1].waker_source, Some(WakerSource::NotFound));
+    }
+
+    #[test]
+    fn unknown_waker_is_reported() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 1, &[(FUTEX, 0, 100)], "futex");
+
+        let path = path(&cca, 1, 8);
+        assert_eq!(path.steps[0].waker_source, Some(WakerSource::NotFound));
+        assert_eq!(path.steps[0].waker_tid, None);
+        assert_eq!(path.waker_not_found_time, ms(100) - ms(0));
+        assert!(cca.critical_path(PID, 9, ms(0), ms(100), &CriticalPathConfig::default()).is_none());
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::model::CpuTimeType;
+
+// stack 中多条栈以 '|' 分隔；以换行分隔的栈帧为由叶到根(jstack 风格)，以 ';' 分隔的为由根到叶(folded 风格)
+const STACK_SEPARATOR: char = '|';
+const NO_STACK_FRAME: &str = "[no stack]";
+
+const SVG_WIDTH: f64 = 1200.0;
+const SVG_PADDING: f64 = 10.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const TITLE_HEIGHT: f64 = 30.0;
+const CHAR_WIDTH: f64 = 7.0;
+
+#[derive(Debug, Clone)]
+pub struct StackQuery {
+    pub pid: u32,
+    pub tid: Option<u32>,
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StackSample {
+    pub tid: u32,
+    pub thread_name: String,
+    pub time_type: CpuTimeType,
+    pub frames: Vec<String>,
+    // 区间落在查询范围内的时长(ns)
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FoldedStacks {
+    pub on_cpu: BTreeMap<String, u64>,
+    pub off_cpu: BTreeMap<String, u64>,
+}
+
+impl FoldedStacks {
+    pub fn from_samples(samples: &[StackSample]) -> Self {
+        let mut folded = FoldedStacks::default();
+        for sample in samples {
+            let mut frames = Vec::with_capacity(sample.frames.len() + 2);
+            frames.push(sample.thread_name.as_str());
+            if sample.frames.is_empty() {
+                frames.push(NO_STACK_FRAME);
+            } else {
+                frames.extend(sample.frames.iter().map(|frame| frame.as_str()));
+            }
+            let reason_frame;
+            let target = if sample.time_type.is_on_cpu() {
+                &mut folded.on_cpu
+            } else {
+                reason_frame = format!("[off_cpu:{}]", sample.time_type.name());
+                frames.push(&reason_frame);
+                &mut folded.off_cpu
+            };
+            *target.entry(frames.join(";")).or_insert(0) += sample.weight;
+        }
+        folded
+    }
+
+    pub fn on_cpu_folded(&self) -> String {
+        to_folded_lines(&self.on_cpu)
+    }
+
+    pub fn off_cpu_folded(&self) -> String {
+        to_folded_lines(&self.off_cpu)
+    }
+}
+
+impl CpuAnalyzer {
+    pub fn stack_samples(&self, query: &StackQuery) -> Vec<StackSample> {
+        let mut samples = Vec::new();
+        let pid_events = match self.pid_events(query.pid) {
+            Some(pid_events) => pid_events,
+            None => return samples,
+        };
+        let tid_cpu_events = pid_events.lock().unwrap();
+        for time_segments in tid_cpu_events.threads.values() {
+            if query.tid.is_some_and(|tid| tid != time_segments.tid) {
+                continue;
+            }
+            time_segments.touch();
+            for event in time_segments.cpu_events_between(query.start_time, query.end_time) {
+                for interval in event.intervals() {
+                    let weight = interval.weighted(interval.overlap(query.start_time, query.end_time));
+                    if weight == 0 {
+                        continue;
+                    }
+                    samples.push(StackSample {
+                        tid: time_segments.tid,
+                        thread_name: time_segments.thread_name_at(interval.start_time).to_string(),
+                        time_type: interval.time_type,
+                        frames: interval.stack,
+                        weight,
+                    });
+                }
+            }
+        }
+        samples
+    }
+
+    pub fn folded_stacks(&self, query: &StackQuery) -> FoldedStacks {
+        FoldedStacks::from_samples(&self.stack_samples(query))
+    }
+}
+
+pub fn parse_stacks(raw: &str) -> Vec<Vec<String>> {
+    if raw.trim().is_empty() {
+        return Vec::new();
+    }
+    raw.split(STACK_SEPARATOR).map(parse_frames).collect()
+}
+
+fn parse_frames(raw: &str) -> Vec<String> {
+    let raw = raw.trim();
+    if raw.contains('\n') {
+        raw.lines().rev()
+            .map(|line| line.trim().trim_start_matches("at ").to_string())
+            .filter(|frame| !frame.is_empty())
+            .collect()
+    } else {
+        raw.split(';')
+            .map(|frame| frame.trim().to_string())
+            .filter(|frame| !frame.is_empty())
+            .collect()
+    }
+}
+
+fn to_folded_lines(stacks: &BTreeMap<String, u64>) -> String {
+    let mut out = String::new();
+    for (stack, weight) in stacks {
+        let _ = writeln!(out, "{} {}", stack, weight);
+    }
+    out
+}
+
+#[derive(Default)]
+struct FrameNode {
+    total: u64,
+    children: BTreeMap<String, FrameNode>,
+}
+
+// 将 folded 栈渲染为不依赖外部资源的 SVG 火焰图
+pub fn render_flame_graph(stacks: &BTreeMap<String, u64>, title: &str) -> String {
+    let mut root = FrameNode::default();
+    for (stack, weight) in stacks {
+        root.total += weight;
+        let mut node = &mut root;
+        for frame in stack.split(';') {
+            node = node.children.entry(frame.to_string()).or_default();
+            node.total += weight;
+        }
+    }
+
+    let depth = max_depth(&root);
+    let height = TITLE_HEIGHT + (depth as f64) * FRAME_HEIGHT + SVG_PADDING * 2.0;
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<?xml version="1.0" standalone="no"?>"#);
+    let _ = writeln!(svg, r#"<svg version="1.1" width="{}" height="{}" viewBox="0 0 {} {}" xmlns="http://www.w3.org/2000/svg">"#, SVG_WIDTH, height, SVG_WIDTH, height);
+    let _ = writeln!(svg, r#"<style>text {{ font-family: Verdana, sans-serif; font-size: 12px; fill: #000; }} rect:hover {{ stroke: #000; stroke-width: 0.5; }}</style>"#);
+    let _ = writeln!(svg, r##"<rect x="0" y="0" width="100%" height="100%" fill="#f8f8f8"/>"##);
+    let _ = writeln!(svg, r#"<text x="{}" y="20" text-anchor="middle" style="font-size: 16px">{}</text>"#, SVG_WIDTH / 2.0, escape_xml(title));
+    if root.total > 0 {
+        let scale = (SVG_WIDTH - SVG_PADDING * 2.0) / root.total as f64;
+        render_children(&mut svg, &root, SVG_PADDING, 0, height - SVG_PADDING, scale, root.total);
+    }
+    let _ = writeln!(svg, "</svg>");
+    svg
+}
+
+fn render_children(svg: &mut String, node: &FrameNode, x: f64, depth: usize, bottom: f64, scale: f64, total: u64) {
+    let mut child_x = x;
+    for (name, child) in node.children.iter() {
+        let width = child.total as f64 * scale;
+        let y = bottom - (depth as f64 + 1.0) * FRAME_HEIGHT;
+        if width >= 0.1 {
+            let percent = child.total as f64 * 100.0 / total as f64;
+            let _ = writeln!(svg, "<g><title>{} ({} ns, {:.2}%)</title>", escape_xml(name), child.total, percent);
+            let _ = writeln!(svg, r#"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}" rx="2" ry="2"/>"#, child_x, y, width, FRAME_HEIGHT - 1.0, frame_color(name));
+            let max_chars = ((width - 6.0) / CHAR_WIDTH) as usize;
+            if max_chars >= 3 {
+                let _ = writeln!(svg, r#"<text x="{:.2}" y="{:.2}">{}</text>"#, child_x + 3.0, y + FRAME_HEIGHT - 4.0, escape_xml(&truncate(name, max_chars)));
+            }
+            let _ = writeln!(svg, "</g>");
+            render_children(svg, child, child_x, depth + 1, bottom, scale, total);
+        }
+        child_x += width;
+    }
+}
+
+fn max_depth(node: &FrameNode) -> usize {
+    node.children.values().map(|child| 1 + max_depth(child)).max().unwrap_or(0)
+}
+
+fn frame_color(name: &str) -> String {
+    let hash = name.bytes().fold(5381u32, |hash, b| hash.wrapping_mul(33) ^ b as u32);
+    if name.starts_with("[off_cpu") {
+        format!("rgb({},{},{})", 80 + hash % 40, 130 + hash % 60, 220)
+    } else {
+        format!("rgb({},{},{})", 205 + hash % 50, 80 + (hash >> 8) % 120, (hash >> 16) % 55)
+    }
+}
+
+fn truncate(name: &str, max_chars: usize) -> String {
+    if name.chars().count() <= max_chars {
+        return name.to_string();
+    }
+    let mut truncated: String = name.chars().take(max_chars.saturating_sub(2)).collect();
+    truncated.push_str("..");
+    truncated
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cpu_analyzer::NANO_TO_SECONDS;
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const BASE: u64 = 1_700_000_000;
+
+    fn sample(thread_name: &str, time_type: CpuTimeType, frames: &[&str], weight: u64) -> StackSample {
+        StackSample {
+            tid: 1,
+            thread_name: thread_name.to_string(),
+            time_type,
+            frames: frames.iter().map(|frame| frame.to_string()).collect(),
+            weight,
+        }
+    }
+
+    #[test]
+    fn jstack_frames_are_reversed_to_root_first() {
+        let raw = "at com.example.Leaf.run()\n  at com.example.Middle.call()\n\tat java.lang.Thread.run()\n";
+        assert_eq!(parse_stacks(raw), vec![vec![
+            "java.lang.Thread.run()".to_string(),
+            "com.example.Middle.call()".to_string(),
+            "com.example.Leaf.run()".to_string(),
+        ]]);
+    }
+
+    #[test]
+    fn folded_frames_keep_their_order() {
+        assert_eq!(parse_stacks("main; run ;;leaf|epoll_wait"), vec![
+            vec!["main".to_string(), "run".to_string(), "leaf".to_string()],
+            vec!["epoll_wait".to_string()],
+        ]);
+        assert!(parse_stacks("  \n ").is_empty());
+    }
+
+    #[test]
+    fn samples_are_folded_by_cpu_state() {
+        let samples = vec![
+            sample("worker", CpuTimeType::On, &["main", "compute"], 30),
+            sample("worker", CpuTimeType::On, &["main", "compute"], 20),
+            sample("worker", CpuTimeType::Futex, &["main", "lock"], 40),
+            sample("worker", CpuTimeType::Net, &[], 10),
+        ];
+        let folded = FoldedStacks::from_samples(&samples);
+        assert_eq!(folded.on_cpu_folded(), "worker;main;compute 50\n");
+        assert_eq!(folded.off_cpu_folded(), "worker;[no stack];[off_cpu:net] 10\nworker;main;lock;[off_cpu:futex] 40\n");
+    }
+
+    #[test]
+    fn stack_samples_follow_event_intervals() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        let start_time = BASE * NANO_TO_SECONDS;
+        cca.put_event_to_segments(1, 10, "worker", "", Box::new(CpuEvent {
+            start_time,
+            end_time: start_time + 300,
+            type_specs: vec![100, 200],
+            time_type: vec![0, 3],
+            stack: "main;compute|at Object.wait()\nat main()".to_string(),
+            ..Default::default()
+        }));
+        let query = StackQuery { pid: 1, tid: None, start_time, end_time: start_time + 200 };
+        let folded = cca.folded_stacks(&query);
+        assert_eq!(folded.on_cpu_folded(), "worker;main;compute 100\n");
+        // off cpu 区间只统计落在查询范围内的部分
+        assert_eq!(folded.off_cpu_folded(), "worker;main();Object.wait();[off_cpu:futex] 100\n");
+        assert!(cca.stack_samples(&StackQuery { tid: Some(11), ..query }).is_empty());
+    }
+
+    #[test]
+    fn flame_graph_escapes_frame_names() {
+        let mut stacks = BTreeMap::new();
+        stacks.insert("main;List<String>.get(\"a&b\")".to_string(), 100);
+        stacks.insert("main;other".to_string(), 100);
+        let svg = render_flame_graph(&stacks, "cpu <on>");
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains("cpu &lt;on&gt;"));
+        assert!(svg.contains("<title>List&lt;String&gt;.get(&quot;a&amp;b&quot;) (100 ns, 50.00%)</title>"));
+        assert!(svg.contains("<title>main (200 ns, 100.00%)</title>"));
+        assert!(!svg.contains("List<String>"));
+        // 背景加上 main 及其两个子帧
+        assert_eq!(svg.matches("<rect x=\"").count(), 4);
+    }
+
+    #[test]
+    fn empty_flame_graph_has_only_title() {
+        let svg = render_flame_graph(&BTreeMap::new(), "empty");
+        assert!(svg.contains(">empty</text>"));
+        assert!(!svg.contains("<g>"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde_derive::Serialize;
+
+// on_info/off_info 中每一段区间的信息以 '|' 分隔，段内字段以 ';' 或 ',' 分隔，
+// 字段形如 key=value 或 key:value，未带 key 的 ip:port 视为对端地址
+const ENTRY_SEPARATOR: char = '|';
+const FIELD_SEPARATORS: [char; 2] = [';', ','];
+const WAKER_TID_FIELD: &str = "waker_tid";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum BlockTarget {
+    File { path: String },
+    Socket { peer: String },
+    Lock { address: String },
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IntervalInfo {
+    pub syscall: Option<String>,
+    pub target: Option<BlockTarget>,
+    pub fields: BTreeMap<String, String>,
+    pub raw: String,
+}
+
+impl IntervalInfo {
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return None;
+        }
+        let mut info = IntervalInfo {
+            raw: raw.to_string(),
+            ..Default::default()
+        };
+        for field in raw.split(&FIELD_SEPARATORS[..]) {
+            let field = field.trim();
+            if field.is_empty() {
+                continue;
+            }
+            match split_field(field) {
+                Some((key, value)) => {
+                    info.fields.insert(key.to_lowercase(), value.to_string());
+                }
+                None if is_socket_address(field) => {
+                    info.fields.entry("peer".to_string()).or_insert_with(|| field.to_string());
+                }
+                None => {
+                    info.fields.entry("syscall".to_string()).or_insert_with(|| field.to_string());
+                }
+            }
+        }
+        info.syscall = first_field(&info.fields, &["syscall", "sc", "call"]);
+        info.target = info.parse_target();
+        Some(info)
+    }
+
+    fn parse_target(&self) -> Option<BlockTarget> {
+        if let Some(path) = first_field(&self.fields, &["file", "path", "filename"]) {
+            return Some(BlockTarget::File { path });
+        }
+        if let Some(peer) = first_field(&self.fields, &["peer", "remote", "dst"]) {
+            return Some(BlockTarget::Socket { peer });
+        }
+        if let Some(ip) = first_field(&self.fields, &["dip", "daddr"]) {
+            let peer = match first_field(&self.fields, &["dport"]) {
+                Some(port) => format!("{}:{}", ip, port),
+                None => ip,
+            };
+            return Some(BlockTarget::Socket { peer });
+        }
+        if let Some(address) = first_field(&self.fields, &["lock", "futex", "uaddr", "addr"]) {
+            return Some(BlockTarget::Lock { address });
+        }
+        None
+    }
+
+    // 唤醒该线程的线程 tid，来自 off_info 中该区间的 waker_tid=<tid> 字段，
+    // 如 "futex;waker_tid=1234"。当前探针不输出该字段，只有上游补充后才会有值
+    pub fn waker_tid(&self) -> Option<u32> {
+        self.fields.get(WAKER_TID_FIELD)?.parse().ok()
+    }
+
+    pub fn describe(&self) -> String {
+        let syscall = self.syscall.as_deref().unwrap_or("");
+        match &self.target {
+            Some(BlockTarget::File { path }) => format!("{} {}", file_verb(syscall), path),
+            Some(BlockTarget::Socket { peer }) => format!("{} {}", socket_verb(syscall), peer),
+            Some(BlockTarget::Lock { address }) => format!("waiting on lock {}", address),
+            None if !syscall.is_empty() => format!("in {}", syscall),
+            None => self.raw.clone(),
+        }
+    }
+}
+
+impl fmt::Display for IntervalInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe())
+    }
+}
+
+// 按顺序拆分出每一段区间对应的信息，空段返回 None 以保持与区间的对应关系
+pub fn parse_interval_infos(raw: &str) -> Vec<Option<IntervalInfo>> {
+    if raw.trim().is_empty() {
+        return Vec::new();
+    }
+    raw.split(ENTRY_SEPARATOR).map(IntervalInfo::parse).collect()
+}
+
+fn split_field(field: &str) -> Option<(&str, &str)> {
+    if let Some((key, value)) = field.split_once('=') {
+        return Some((key.trim(), value.trim()));
+    }
+    // key:value 仅在 key 为标识符时成立，避免把 ip:port 拆开
+    let (key, value) = field.split_once(':')?;
+    let key = key.trim();
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+        Some((key, value.trim()))
+    } else {
+        None
+    }
+}
+
+fn is_socket_address(field: &str) -> bool {
+    match field.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+fn first_field(fields: &BTreeMap<String, String>, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .filter_map(|key| fields.get(*key))
+        .find(|value| !value.is_empty())
+        .cloned()
+}
+
+fn file_verb(syscall: &str) -> &'static str {
+    if is_write_syscall(syscall) {
+        "writing to"
+    } else if syscall.starts_with("open") {
+        "opening"
+    } else {
+        "reading from"
+    }
+}
+
+fn socket_verb(syscall: &str) -> &'static str {
+    if syscall == "connect" {
+        "connecting to"
+    } else if syscall.starts_with("accept") {
+        "accepting from"
+    } else if is_write_syscall(syscall) {
+        "writing to"
+    } else {
+        "reading from"
+    }
+}
+
+fn is_write_syscall(syscall: &str) -> bool {
+    syscall.contains("write") || syscall.starts_with("send") || syscall == "fsync" || syscall == "fdatasync"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_file_target() {
+        let info = IntervalInfo::parse("syscall=write; file=/var/log/app.log").unwrap();
+        assert_eq!(info.syscall.as_deref(), Some("write"));
+        assert_eq!(info.target, Some(BlockTarget::File { path: "/var/log/app.log".to_string() }));
+        assert_eq!(info.describe(), "writing to /var/log/app.log");
+    }
+
+    #[test]
+    fn parse_bare_syscall_and_peer() {
+        let info = IntervalInfo::parse("recvfrom,10.0.0.1:3306").unwrap();
+        assert_eq!(info.syscall.as_deref(), Some("recvfrom"));
+        assert_eq!(info.target, Some(BlockTarget::Socket { peer: "10.0.0.1:3306".to_string() }));
+        assert_eq!(info.describe(), "reading from 10.0.0.1:3306");
+    }
+
+    #[test]
+    fn parse_colon_fields_and_dip_dport() {
+        let info = IntervalInfo::parse("sc:connect;dip:192.168.1.2;dport:80").unwrap();
+        assert_eq!(info.syscall.as_deref(), Some("connect"));
+        assert_eq!(info.target, Some(BlockTarget::Socket { peer: "192.168.1.2:80".to_string() }));
+        assert_eq!(info.describe(), "connecting to 192.168.1.2:80");
+    }
+
+    #[test]
+    fn parse_lock_and_waker_tid() {
+        let info = IntervalInfo::parse("futex;uaddr=0x7f00;waker_tid=1234").unwrap();
+        assert_eq!(info.target, Some(BlockTarget::Lock { address: "0x7f00".to_string() }));
+        assert_eq!(info.waker_tid(), Some(1234));
+        assert_eq!(IntervalInfo::parse("futex").unwrap().waker_tid(), None);
+    }
+
+    #[test]
+    fn parse_empty_and_unknown() {
+        assert!(IntervalInfo::parse("  ").is_none());
+        let info = IntervalInfo::parse("epoll_wait").unwrap();
+        assert_eq!(info.target, None);
+        assert_eq!(info.describe(), "in epoll_wait");
+    }
+
+    #[test]
+    fn parse_entries_keeps_positions() {
+        let infos = parse_interval_infos("read;file=/a||write;file=/b");
+        assert_eq!(infos.len(), 3);
+        assert!(infos[1].is_none());
+        assert_eq!(infos[2].as_ref().unwrap().target, Some(BlockTarget::File { path: "/b".to_string() }));
+        assert!(parse_interval_infos("").is_empty());
+    }
+}
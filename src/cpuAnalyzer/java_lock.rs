@@ -0,0 +1,397 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::model::{CpuInterval, CpuTimeType, JavaFutexEvent};
+
+// data_val 支持 key=value 形式(以 ';'、',' 或 '!' 分隔)，
+// 或按位置以 '!' 分隔的 "锁名!持有线程tid!持有线程名"
+const FIELD_SEPARATORS: [char; 3] = [';', ',', '!'];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct JavaLockInfo {
+    pub lock: String,
+    pub owner_tid: Option<u32>,
+    pub owner_name: Option<String>,
+    pub raw: String,
+}
+
+impl JavaLockInfo {
+    pub fn parse(data_val: &str) -> Self {
+        let raw = data_val.trim();
+        let mut info = JavaLockInfo {
+            raw: raw.to_string(),
+            ..Default::default()
+        };
+        if raw.contains('=') {
+            for field in raw.split(&FIELD_SEPARATORS[..]) {
+                let (key, value) = match field.split_once('=') {
+                    Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+                    None => continue,
+                };
+                match key.as_str() {
+                    "lock" | "monitor" | "lock_name" | "class" => info.lock = value.to_string(),
+                    "owner" | "owner_tid" | "holder" | "holder_tid" => info.set_owner(value),
+                    "owner_name" | "holder_name" => info.owner_name = Some(value.to_string()),
+                    _ => (),
+                }
+            }
+        } else {
+            let mut fields = raw.split('!').map(|field| field.trim());
+            info.lock = fields.next().unwrap_or_default().to_string();
+            if let Some(owner) = fields.next() {
+                info.set_owner(owner);
+            }
+            if let Some(owner_name) = fields.next().filter(|name| !name.is_empty()) {
+                info.owner_name = Some(owner_name.to_string());
+            }
+        }
+        if info.lock.is_empty() {
+            info.lock = raw.to_string();
+        }
+        info
+    }
+
+    fn set_owner(&mut self, value: &str) {
+        match value.parse::<u32>() {
+            Ok(tid) => self.owner_tid = Some(tid),
+            Err(_) if !value.is_empty() => self.owner_name = Some(value.to_string()),
+            Err(_) => (),
+        }
+    }
+}
+
+impl fmt::Display for JavaLockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "waiting on {}", self.lock)?;
+        match (&self.owner_tid, &self.owner_name) {
+            (Some(tid), Some(name)) => write!(f, " held by {} ({})", tid, name),
+            (Some(tid), None) => write!(f, " held by {}", tid),
+            (None, Some(name)) => write!(f, " held by {}", name),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+// 为每个 futex 类型的 off cpu 区间找到同线程上重叠最多的 JavaFutexEvent
+pub fn correlate_java_futex(intervals: &mut [CpuInterval], futex_events: &[JavaFutexEvent]) {
+    for interval in intervals.iter_mut() {
+        if interval.time_type != CpuTimeType::Futex {
+            continue;
+        }
+        let matched = futex_events.iter()
+            .map(|event| (interval.overlap(event.start_time, event.end_time), event))
+            .filter(|(overlap, _)| *overlap > 0)
+            .max_by_key(|(overlap, _)| *overlap);
+        if let Some((_, event)) = matched {
+            interval.java_lock = Some(JavaLockInfo::parse(&event.data_val));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockHolder {
+    pub tid: Option<u32>,
+    pub thread_name: Option<String>,
+    // 其他线程因该持有者而阻塞的总时长(ns)
+    pub blocked_time: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockContention {
+    pub lock: String,
+    pub total_blocked_time: u64,
+    pub wait_count: usize,
+    pub waiters: Vec<u32>,
+    pub holders: Vec<LockHolder>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockContentionReport {
+    pub pid: u32,
+    pub start_time: u64,
+    pub end_time: u64,
+    // 按总阻塞时长降序
+    pub locks: Vec<LockContention>,
+}
+
+impl LockContentionReport {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl fmt::Display for LockContentionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<48} {:>14} {:>8} {:>8}  HOLDERS", "LOCK", "BLOCKED(ms)", "WAITS", "WAITERS")?;
+        for contention in self.locks.iter() {
+            let holders: Vec<String> = contention.holders.iter()
+                .map(|holder| {
+                    let name = match (&holder.tid, &holder.thread_name) {
+                        (Some(tid), Some(name)) => format!("{}({})", name, tid),
+                        (Some(tid), None) => tid.to_string(),
+                        (None, Some(name)) => name.clone(),
+                        (None, None) => "unknown".to_string(),
+                    };
+                    format!("{} {:.1}ms", name, holder.blocked_time as f64 / 1_000_000.0)
+                })
+                .collect();
+            writeln!(f, "{:<48} {:>14.1} {:>8} {:>8}  {}",
+                     contention.lock,
+                     contention.total_blocked_time as f64 / 1_000_000.0,
+                     contention.wait_count,
+                     contention.waiters.len(),
+                     holders.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct LockStats {
+    total_blocked_time: u64,
+    wait_count: usize,
+    waiters: BTreeSet<u32>,
+    holders: HashMap<(Option<u32>, Option<String>), u64>,
+}
+
+impl CpuAnalyzer {
+    pub fn lock_contention_report(&self, pid: u32, start_time: u64, end_time: u64) -> LockContentionReport {
+        let mut stats: HashMap<String, LockStats> = HashMap::new();
+        let mut thread_names: HashMap<u32, String> = HashMap::new();
+        if let Some(pid_events) = self.pid_events(pid) {
+            let tid_cpu_events = pid_events.lock().unwrap();
+            for time_segments in tid_cpu_events.threads.values() {
+                thread_names.insert(time_segments.tid, time_segments.thread_name.clone());
+                for event in time_segments.java_futex_events_between(start_time, end_time) {
+                    let blocked_time = event.end_time.min(end_time).saturating_sub(event.start_time.max(start_time));
+                    let blocked_time = (blocked_time as f64 * event.sample_weight).round() as u64;
+                    let info = event.lock_info();
+                    let lock_stats = stats.entry(info.lock.clone()).or_default();
+                    lock_stats.total_blocked_time += blocked_time;
+                    lock_stats.wait_count += event.sample_weight.round() as usize;
+                    lock_stats.waiters.insert(time_segments.tid);
+                    if info.owner_tid.is_some() || info.owner_name.is_some() {
+                        *lock_stats.holders.entry((info.owner_tid, info.owner_name)).or_insert(0) += blocked_time;
+                    }
+                }
+            }
+        }
+
+        let mut locks: Vec<LockContention> = stats.into_iter()
+            .map(|(lock, lock_stats)| {
+                let mut holders: Vec<LockHolder> = lock_stats.holders.into_iter()
+                    .map(|((tid, name), blocked_time)| LockHolder {
+                        tid,
+                        thread_name: name.or_else(|| tid.and_then(|tid| thread_names.get(&tid).cloned())),
+                        blocked_time,
+                    })
+                    .collect();
+                holders.sort_by_key(|holder| Reverse(holder.blocked_time));
+                LockContention {
+                    lock,
+                    total_blocked_time: lock_stats.total_blocked_time,
+                    wait_count: lock_stats.wait_count,
+                    waiters: lock_stats.waiters.into_iter().collect(),
+                    holders,
+                }
+            })
+            .collect();
+        locks.sort_by(|a, b| b.total_blocked_time.cmp(&a.total_blocked_time).then_with(|| a.lock.cmp(&b.lock)));
+
+        LockContentionReport {
+            pid,
+            start_time,
+            end_time,
+            locks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cpu_analyzer::NANO_TO_SECONDS;
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+
+    // 相对 BASE 的毫秒
+    fn ms(val: u64) -> u64 {
+        BASE * NANO_TO_SECONDS + val * 1_000_000
+    }
+
+    #[test]
+    fn parse_key_value_fields() {
+        let info = JavaLockInfo::parse("lock=java.util.concurrent.locks.ReentrantLock; owner=123, owner_name=worker-1");
+        assert_eq!(info.lock, "java.util.concurrent.locks.ReentrantLock");
+        assert_eq!(info.owner_tid, Some(123));
+        assert_eq!(info.owner_name.as_deref(), Some("worker-1"));
+        assert_eq!(info.to_string(), "waiting on java.util.concurrent.locks.ReentrantLock held by 123 (worker-1)");
+    }
+
+    #[test]
+    fn parse_key_value_with_bang_and_named_holder() {
+        let info = JavaLockInfo::parse("monitor=com.foo.Cache!holder=main");
+        assert_eq!(info.lock, "com.foo.Cache");
+        assert_eq!(info.owner_tid, None);
+        assert_eq!(info.owner_name.as_deref(), Some("main"));
+        assert_eq!(info.to_string(), "waiting on com.foo.Cache held by main");
+    }
+
+    #[test]
+    fn parse_positional_fields() {
+        let info = JavaLockInfo::parse("com.foo.Cache!456!pool-1-thread-2");
+        assert_eq!(info.lock, "com.foo.Cache");
+        assert_eq!(info.owner_tid, Some(456));
+        assert_eq!(info.owner_name.as_deref(), Some("pool-1-thread-2"));
+
+        let info = JavaLockInfo::parse("com.foo.Cache!!");
+        assert_eq!(info.owner_tid, None);
+        assert_eq!(info.owner_name, None);
+        assert_eq!(info.to_string(), "waiting on com.foo.Cache");
+    }
+
+    #[test]
+    fn parse_without_lock_falls_back_to_raw() {
+        let info = JavaLockInfo::parse(" owner=7 ");
+        assert_eq!(info.lock, "owner=7");
+        assert_eq!(info.owner_tid, Some(7));
+        assert_eq!(info.raw, "owner=7");
+    }
+
+    fn interval(time_type: CpuTimeType, start_time: u64, end_time: u64) -> CpuInterval {
+        CpuInterval {
+            start_time,
+            end_time,
+            time_type,
+            runq_latency: 0,
+            info: None,
+            java_lock: None,
+            stack: Vec::new(),
+            sample_weight: 1.0,
+        }
+    }
+
+    fn futex_event(start_time: u64, end_time: u64, data_val: &str) -> JavaFutexEvent {
+        JavaFutexEvent {
+            start_time,
+            end_time,
+            data_val: data_val.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn lock_of(interval: &CpuInterval) -> Option<&str> {
+        interval.java_lock.as_ref().map(|info| info.lock.as_str())
+    }
+
+    #[test]
+    fn largest_overlap_wins() {
+        let mut intervals = vec![interval(CpuTimeType::Futex, 100, 200)];
+        let events = vec![
+            futex_event(50, 120, "lock=A"),
+            futex_event(130, 300, "lock=B"),
+            futex_event(190, 250, "lock=C"),
+        ];
+        correlate_java_futex(&mut intervals, &events);
+        assert_eq!(lock_of(&intervals[0]), Some("B"));
+    }
+
+    #[test]
+    fn non_futex_intervals_are_untouched() {
+        let mut intervals = vec![
+            interval(CpuTimeType::On, 0, 100),
+            interval(CpuTimeType::File, 100, 200),
+            interval(CpuTimeType::Futex, 200, 300),
+        ];
+        correlate_java_futex(&mut intervals, &[futex_event(0, 300, "lock=A")]);
+        assert_eq!(lock_of(&intervals[0]), None);
+        assert_eq!(lock_of(&intervals[1]), None);
+        assert_eq!(lock_of(&intervals[2]), Some("A"));
+    }
+
+    #[test]
+    fn no_overlap_leaves_lock_empty() {
+        // 首尾相接不算重叠
+        let mut intervals = vec![interval(CpuTimeType::Futex, 100, 200)];
+        correlate_java_futex(&mut intervals, &[futex_event(0, 100, "lock=A"), futex_event(200, 300, "lock=B")]);
+        assert_eq!(lock_of(&intervals[0]), None);
+        correlate_java_futex(&mut intervals, &[]);
+        assert_eq!(lock_of(&intervals[0]), None);
+    }
+
+    fn put_wait(cca: &CpuAnalyzer, tid: u32, start_ms: u64, end_ms: u64, data_val: &str, sample_weight: f64) {
+        cca.put_event_to_segments(PID, tid, &format!("worker-{}", tid), "", Box::new(JavaFutexEvent {
+            sample_weight,
+            ..futex_event(ms(start_ms), ms(end_ms), data_val)
+        }));
+    }
+
+    fn contention_analyzer() -> CpuAnalyzer {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        // 持有者 20 有自己的数据，报告中用它的线程名
+        cca.put_event_to_segments(PID, 20, "holder-20", "", Box::new(CpuEvent {
+            start_time: ms(0),
+            end_time: ms(100),
+            type_specs: vec![ms(100) - ms(0)],
+            time_type: vec![0],
+            ..Default::default()
+        }));
+        put_wait(&cca, 10, 0, 100, "lock=A;owner=20", 1.0);
+        put_wait(&cca, 10, 200, 250, "lock=A;owner=21", 1.0);
+        put_wait(&cca, 11, 0, 50, "lock=A;owner=20", 1.0);
+        put_wait(&cca, 11, 300, 310, "lock=B;owner=main", 2.0);
+        put_wait(&cca, 12, 400, 401, "lock=C", 1.0);
+        cca
+    }
+
+    #[test]
+    fn locks_are_ranked_by_blocked_time() {
+        let cca = contention_analyzer();
+        let report = cca.lock_contention_report(PID, ms(0), ms(1000));
+        let locks: Vec<(&str, u64, usize, &[u32])> = report.locks.iter()
+            .map(|lock| (lock.lock.as_str(), lock.total_blocked_time, lock.wait_count, lock.waiters.as_slice()))
+            .collect();
+        assert_eq!(locks, vec![
+            ("A", ms(200) - ms(0), 3, &[10, 11][..]),
+            ("B", ms(20) - ms(0), 2, &[11][..]),
+            ("C", ms(1) - ms(0), 1, &[12][..]),
+        ]);
+
+        let holders: Vec<(Option<u32>, Option<&str>, u64)> = report.locks[0].holders.iter()
+            .map(|holder| (holder.tid, holder.thread_name.as_deref(), holder.blocked_time))
+            .collect();
+        assert_eq!(holders, vec![(Some(20), Some("holder-20"), ms(150) - ms(0)), (Some(21), None, ms(50) - ms(0))]);
+        assert_eq!(report.locks[1].holders[0].thread_name.as_deref(), Some("main"));
+        assert!(report.locks[2].holders.is_empty());
+    }
+
+    #[test]
+    fn waits_are_clipped_to_range() {
+        let cca = contention_analyzer();
+        let report = cca.lock_contention_report(PID, ms(25), ms(75));
+        assert_eq!(report.locks.len(), 1);
+        assert_eq!(report.locks[0].total_blocked_time, ms(75) - ms(0));
+        assert_eq!(report.locks[0].holders[0].blocked_time, ms(75) - ms(0));
+        assert!(cca.lock_contention_report(PID + 1, ms(0), ms(1000)).locks.is_empty());
+    }
+
+    #[test]
+    fn report_table_lists_locks_in_order() {
+        let cca = contention_analyzer();
+        let table = cca.lock_contention_report(PID, ms(0), ms(1000)).to_string();
+        let rows: Vec<Vec<&str>> = table.lines().map(|line| line.split_whitespace().collect()).collect();
+        assert_eq!(rows, vec![
+            vec!["LOCK", "BLOCKED(ms)", "WAITS", "WAITERS", "HOLDERS"],
+            vec!["A", "200.0", "3", "2", "holder-20(20)", "150.0ms,", "21", "50.0ms"],
+            vec!["B", "20.0", "2", "1", "main", "20.0ms"],
+            vec!["C", "1.0", "1", "1"],
+        ]);
+        // 锁名按固定宽度对齐
+        assert!(table.lines().nth(1).unwrap().starts_with(&format!("{:<48} ", "A")));
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde_derive::Serialize;
+use serde_json::Value;
+use crate::cpuAnalyzer::model::CpuInterval;
+
+// 不带时区的时间按本地时间解析
+const NAIVE_TIME_FORMATS: [&str; 4] = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y/%m/%d %H:%M:%S%.f", "%Y-%m-%d %H:%M:%S,%f"];
+const LEVELS: [&str; 8] = ["TRACE", "DEBUG", "INFO", "WARN", "WARNING", "ERROR", "FATAL", "CRITICAL"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogRecord {
+    // 日志行中的时间(ns)，没有或无法解析时使用所属事件的开始时间
+    pub timestamp: u64,
+    pub level: Option<String>,
+    pub message: String,
+    pub fields: BTreeMap<String, String>,
+    pub raw: String,
+}
+
+impl LogRecord {
+    // 支持 json 格式的日志行，以及 "时间 级别 消息 key=value" 形式的文本日志，各部分均可省略
+    pub fn parse(line: &str, default_timestamp: u64) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        if line.starts_with('{') {
+            if let Ok(Value::Object(object)) = serde_json::from_str::<Value>(line) {
+                return Some(Self::from_json(line, object, default_timestamp));
+            }
+        }
+        Some(Self::from_text(line, default_timestamp))
+    }
+
+    fn from_json(line: &str, object: serde_json::Map<String, Value>, default_timestamp: u64) -> Self {
+        let mut record = LogRecord {
+            timestamp: default_timestamp,
+            level: None,
+            message: String::new(),
+            fields: BTreeMap::new(),
+            raw: line.to_string(),
+        };
+        for (key, value) in object {
+            let text = match value {
+                Value::String(text) => text,
+                other => other.to_string(),
+            };
+            match key.to_lowercase().as_str() {
+                "ts" | "time" | "timestamp" | "@timestamp" => match parse_timestamp(&text) {
+                    Some(timestamp) => record.timestamp = timestamp,
+                    None => {
+                        record.fields.insert(key, text);
+                    }
+                },
+                "level" | "lvl" | "severity" | "loglevel" => record.level = Some(text.to_uppercase()),
+                "msg" | "message" => record.message = text,
+                _ => {
+                    record.fields.insert(key, text);
+                }
+            }
+        }
+        record
+    }
+
+    fn from_text(line: &str, default_timestamp: u64) -> Self {
+        let mut rest = line;
+        let mut timestamp = None;
+        // 时间可能占一个或两个以空白分隔的部分，中间的空白数量不定
+        let tokens: Vec<&str> = rest.split_whitespace().take(2).collect();
+        if tokens.len() == 2 {
+            if let Some(ts) = parse_timestamp(&format!("{} {}", tokens[0], tokens[1])) {
+                timestamp = Some(ts);
+                rest = text_after(rest, tokens[1]);
+            }
+        }
+        if timestamp.is_none() {
+            if let Some(ts) = tokens.first().and_then(|token| parse_timestamp(token)) {
+                timestamp = Some(ts);
+                rest = text_after(rest, tokens[0]);
+            }
+        }
+
+        let mut level = None;
+        if let Some(token) = rest.split_whitespace().next() {
+            let candidate = token.trim_matches(|c| c == '[' || c == ']' || c == ':').to_uppercase();
+            if LEVELS.contains(&candidate.as_str()) {
+                level = Some(candidate);
+                rest = rest[token.len()..].trim_start();
+            }
+        }
+
+        let mut fields = BTreeMap::new();
+        for token in rest.split_whitespace() {
+            if let Some((key, value)) = token.split_once('=') {
+                if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+                    fields.insert(key.to_string(), value.trim_matches('"').to_string());
+                }
+            }
+        }
+
+        LogRecord {
+            timestamp: timestamp.unwrap_or(default_timestamp),
+            level,
+            message: rest.to_string(),
+            fields,
+            raw: line.to_string(),
+        }
+    }
+}
+
+// token 为 text 的子串，返回其后的内容
+fn text_after<'a>(text: &'a str, token: &str) -> &'a str {
+    let end = token.as_ptr() as usize - text.as_ptr() as usize + token.len();
+    text[end..].trim_start()
+}
+
+// 每行一条日志
+pub fn parse_log_records(log: &str, default_timestamp: u64) -> Vec<LogRecord> {
+    log.lines().filter_map(|line| LogRecord::parse(line, default_timestamp)).collect()
+}
+
+// RFC 3339、常见的不带时区格式，或 epoch 秒/毫秒/微秒/纳秒
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+        let value: u64 = text.parse().ok()?;
+        return match text.len() {
+            10 => Some(value * 1_000_000_000),
+            13 => Some(value * 1_000_000),
+            16 => Some(value * 1_000),
+            19 => Some(value),
+            _ => None,
+        };
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return u64::try_from(time.timestamp_nanos()).ok();
+    }
+    for format in NAIVE_TIME_FORMATS.iter() {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(text, format) {
+            let local = Local.from_local_datetime(&naive).earliest()?;
+            return u64::try_from(local.timestamp_nanos()).ok();
+        }
+    }
+    None
+}
+
+// 按时间交错排列的 on/off cpu 区间与日志
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub enum TimelineEntry {
+    Interval(CpuInterval),
+    Log(LogRecord),
+}
+
+impl TimelineEntry {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            TimelineEntry::Interval(interval) => interval.start_time,
+            TimelineEntry::Log(record) => record.timestamp,
+        }
+    }
+}
+
+pub fn interleave(intervals: Vec<CpuInterval>, logs: Vec<LogRecord>) -> Vec<TimelineEntry> {
+    let mut entries: Vec<TimelineEntry> = intervals.into_iter().map(TimelineEntry::Interval)
+        .chain(logs.into_iter().map(TimelineEntry::Log))
+        .collect();
+    entries.sort_by_key(|entry| entry.timestamp());
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_TS: u64 = 42;
+
+    #[test]
+    fn parse_json_line() {
+        let record = LogRecord::parse(r#"{"ts":"2024-01-01T00:00:01Z","level":"warn","msg":"slow query","cost":120}"#, DEFAULT_TS).unwrap();
+        assert_eq!(record.timestamp, 1_704_067_201_000_000_000);
+        assert_eq!(record.level.as_deref(), Some("WARN"));
+        assert_eq!(record.message, "slow query");
+        assert_eq!(record.fields.get("cost").map(String::as_str), Some("120"));
+    }
+
+    #[test]
+    fn parse_json_without_time_uses_default() {
+        let record = LogRecord::parse(r#"{"message":"hi","time":"later"}"#, DEFAULT_TS).unwrap();
+        assert_eq!(record.timestamp, DEFAULT_TS);
+        assert_eq!(record.message, "hi");
+        assert_eq!(record.fields.get("time").map(String::as_str), Some("later"));
+    }
+
+    #[test]
+    fn parse_text_with_epoch() {
+        let record = LogRecord::parse("1700000000 ERROR boom user=1 trace_id=\"abc\"", DEFAULT_TS).unwrap();
+        assert_eq!(record.timestamp, 1_700_000_000_000_000_000);
+        assert_eq!(record.level.as_deref(), Some("ERROR"));
+        assert_eq!(record.message, "boom user=1 trace_id=\"abc\"");
+        assert_eq!(record.fields.get("user").map(String::as_str), Some("1"));
+        assert_eq!(record.fields.get("trace_id").map(String::as_str), Some("abc"));
+    }
+
+    #[test]
+    fn parse_epoch_precisions() {
+        for (text, expected) in [
+            ("1700000000123", 1_700_000_000_123_000_000),
+            ("1700000000123456", 1_700_000_000_123_456_000),
+            ("1700000000123456789", 1_700_000_000_123_456_789),
+        ] {
+            assert_eq!(LogRecord::parse(&format!("{} x", text), DEFAULT_TS).unwrap().timestamp, expected);
+        }
+        // 位数不符合任何精度时不视为时间
+        let record = LogRecord::parse("12345 x", DEFAULT_TS).unwrap();
+        assert_eq!(record.timestamp, DEFAULT_TS);
+        assert_eq!(record.message, "12345 x");
+    }
+
+    #[test]
+    fn parse_text_with_multiple_spaces() {
+        let record = LogRecord::parse("2024-01-01  12:00:01   [WARN]  x", DEFAULT_TS).unwrap();
+        assert_ne!(record.timestamp, DEFAULT_TS);
+        assert_eq!(record.level.as_deref(), Some("WARN"));
+        assert_eq!(record.message, "x");
+    }
+
+    #[test]
+    fn parse_text_without_time_or_level() {
+        let record = LogRecord::parse("just a message", DEFAULT_TS).unwrap();
+        assert_eq!(record.timestamp, DEFAULT_TS);
+        assert_eq!(record.level, None);
+        assert_eq!(record.message, "just a message");
+        assert!(LogRecord::parse("   ", DEFAULT_TS).is_none());
+        assert_eq!(parse_log_records("a\n\nb", DEFAULT_TS).len(), 2);
+    }
+}
@@ -2,8 +2,40 @@ mod circle_queue;
 mod model;
 mod cpu_analyzer;
 mod time_event;
+mod runq_latency;
+mod interval_info;
+mod flame_graph;
+mod pprof;
+mod java_lock;
+mod sink;
+mod query;
+mod snapshot;
+mod reorder;
+mod clock;
+mod critical_path;
+mod thread_pool;
+mod anomaly;
+mod rate_limit;
+mod log_record;
+mod cgroup;
 
 pub use cpu_analyzer::{consume_cpu_event, consume_java_futex_event};
-pub use cpu_analyzer::CpuAnalyzer;
+pub use cpu_analyzer::{CpuAnalyzer, CpuAnalyzerConfig, ExportOptions};
+pub use reorder::AnalyzerStats;
+pub use cgroup::{CgroupCollector, CgroupThrottleEvent, CpuStat, CGROUP_THROTTLE_EVENT_KIND};
+pub use clock::{ClockConfig, ClockDomain, ClockSync};
+pub use rate_limit::RateLimitConfig;
+pub use runq_latency::{RunqLatencyConfig, RunqLatencyReport};
+pub use interval_info::{BlockTarget, IntervalInfo};
+pub use flame_graph::{render_flame_graph, FoldedStacks, StackQuery, StackSample};
+pub use critical_path::{CriticalPath, CriticalPathConfig, CriticalPathStep, ThreadAttribution, WakerSource};
+pub use thread_pool::{normalize_thread_name, ThreadPoolConfig, ThreadPoolPattern, ThreadPoolReport, ThreadPoolStats};
+pub use anomaly::{Anomaly, AnomalyConfig, AnomalyDetector, AnomalyMetric};
+pub use java_lock::{JavaLockInfo, LockContention, LockContentionReport};
+pub use log_record::{parse_log_records, LogRecord, TimelineEntry};
+pub use query::{SegmentQuery, TimeSegmentsSnapshot};
+pub use sink::{ExportedEvent, FileSink, MemorySink, SegmentRecord, SegmentSink, StdoutJsonSink, ANOMALY_RECORD_TYPE, SEGMENT_RECORD_TYPE};
+pub use time_event::{register_event_kind, TimedEvent, CPU_EVENT_KIND, JAVA_FUTEX_EVENT_KIND};
+pub use model::{CpuEvent, CpuInterval, CpuTimeType, JavaFutexEvent, Segment, ThreadNameChange, TimeSegments};
 
 pub use cpu_analyzer::print_all_event;
\ No newline at end of file
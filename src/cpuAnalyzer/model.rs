@@ -1,12 +1,21 @@
 use std::any::Any;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::fmt;
 use std::fmt::{Debug, Formatter};
-use chrono::{DateTime, Local};
+use chrono::Utc;
 use libc::sleep;
 use serde_derive::Serialize;
 use serde_derive::Deserialize;
 use crate::cpuAnalyzer::circle_queue::CircleQueue;
-use crate::cpuAnalyzer::time_event::TimedEvent;
+use crate::cpuAnalyzer::cpu_analyzer::NANO_TO_SECONDS;
+use crate::cpuAnalyzer::interval_info::{parse_interval_infos, IntervalInfo};
+use crate::cpuAnalyzer::flame_graph::parse_stacks;
+use crate::cpuAnalyzer::java_lock::{correlate_java_futex, JavaLockInfo};
+use crate::cpuAnalyzer::log_record::{interleave, parse_log_records, LogRecord, TimelineEntry};
+use crate::cpuAnalyzer::time_event::{serde_events, TimedEvent, CPU_EVENT_KIND, JAVA_FUTEX_EVENT_KIND};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CpuEvent {
@@ -26,6 +35,21 @@ pub struct CpuEvent {
     pub off_info: String,
     pub log: String,
     pub stack: String,
+    // 限流采样后每个保留的事件代表的原始事件数，聚合时按此放大
+    #[serde(rename = "sampleWeight", default = "default_sample_weight")]
+    pub sample_weight: f64,
+    // 探针时间戳无法换算到 realtime，start_time/end_time 为收到事件时的估计值
+    #[serde(rename = "clockUnreconciled", default)]
+    pub clock_unreconciled: bool,
+    // clock_unreconciled 时保存探针给出的原始时间戳，便于按其他时钟重新解释
+    #[serde(rename = "rawStartTime", default, skip_serializing_if = "Option::is_none")]
+    pub raw_start_time: Option<u64>,
+    #[serde(rename = "rawEndTime", default, skip_serializing_if = "Option::is_none")]
+    pub raw_end_time: Option<u64>,
+}
+
+fn default_sample_weight() -> f64 {
+    1.0
 }
 
 impl Default for CpuEvent {
@@ -40,6 +64,10 @@ impl Default for CpuEvent {
             off_info: String::new(),
             log: String::new(),
             stack: String::new(),
+            sample_weight: 1.0,
+            clock_unreconciled: false,
+            raw_start_time: None,
+            raw_end_time: None,
         }
     }
 }
@@ -52,6 +80,142 @@ impl fmt::Display for CpuEvent {
     }
 }
 
+impl CpuEvent {
+    // time_specs 中依次记录每一段 on/off cpu 的持续时间(ns)，time_type 与之一一对应，
+    // runq_latency 按下标对应该段开始前在运行队列中的等待时间(ns)
+    // on_info/off_info 按顺序分别对应 on cpu 与 off cpu 区间；
+    // stack 只有一条时为整个事件共用，否则按下标对应每个区间
+    pub fn intervals(&self) -> Vec<CpuInterval> {
+        let mut on_infos = parse_interval_infos(&self.on_info).into_iter();
+        let mut off_infos = parse_interval_infos(&self.off_info).into_iter();
+        let stacks = parse_stacks(&self.stack);
+        let mut intervals = Vec::with_capacity(self.type_specs.len());
+        let mut start_time = self.start_time;
+        for (i, spec) in self.type_specs.iter().enumerate() {
+            let end_time = start_time + spec;
+            let time_type = CpuTimeType::from_u8(self.time_type.get(i).copied().unwrap_or(u8::MAX));
+            let info = if time_type.is_on_cpu() {
+                on_infos.next().flatten()
+            } else {
+                off_infos.next().flatten()
+            };
+            intervals.push(CpuInterval {
+                start_time,
+                end_time,
+                time_type,
+                runq_latency: self.runq_latency.get(i).copied().unwrap_or(0),
+                info,
+                java_lock: None,
+                sample_weight: self.sample_weight,
+                stack: if stacks.len() == 1 { stacks[0].clone() } else { stacks.get(i).cloned().unwrap_or_default() },
+            });
+            start_time = end_time;
+        }
+        intervals
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CpuTimeType {
+    On,
+    File,
+    Net,
+    Futex,
+    Idle,
+    Other,
+    Epoll,
+    Unknown(u8),
+}
+
+impl CpuTimeType {
+    pub fn from_u8(val: u8) -> Self {
+        match val {
+            0 => CpuTimeType::On,
+            1 => CpuTimeType::File,
+            2 => CpuTimeType::Net,
+            3 => CpuTimeType::Futex,
+            4 => CpuTimeType::Idle,
+            5 => CpuTimeType::Other,
+            6 => CpuTimeType::Epoll,
+            _ => CpuTimeType::Unknown(val),
+        }
+    }
+
+    pub fn is_on_cpu(&self) -> bool {
+        *self == CpuTimeType::On
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuTimeType::On => "on",
+            CpuTimeType::File => "file",
+            CpuTimeType::Net => "net",
+            CpuTimeType::Futex => "futex",
+            CpuTimeType::Idle => "idle",
+            CpuTimeType::Other => "other",
+            CpuTimeType::Epoll => "epoll",
+            CpuTimeType::Unknown(_) => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CpuInterval {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub time_type: CpuTimeType,
+    pub runq_latency: u64,
+    pub info: Option<IntervalInfo>,
+    // futex 区间关联上的 java 锁，见 correlate_java_futex
+    pub java_lock: Option<JavaLockInfo>,
+    // 由根到叶的栈帧，没有栈信息时为空
+    pub stack: Vec<String>,
+    // 所属事件的采样权重，见 CpuEvent::sample_weight
+    pub sample_weight: f64,
+}
+
+impl CpuInterval {
+    pub fn duration(&self) -> u64 {
+        self.end_time.saturating_sub(self.start_time)
+    }
+
+    // 与 [start_time, end_time) 重叠部分的时长
+    pub fn overlap(&self, start_time: u64, end_time: u64) -> u64 {
+        let start = self.start_time.max(start_time);
+        let end = self.end_time.min(end_time);
+        end.saturating_sub(start)
+    }
+
+    // 按采样权重放大后的时长，用于汇总
+    pub fn weighted(&self, time: u64) -> u64 {
+        (time as f64 * self.sample_weight).round() as u64
+    }
+}
+
+impl fmt::Display for CpuInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = self.duration() as f64 / 1_000_000.0;
+        if self.time_type.is_on_cpu() {
+            write!(f, "on cpu {:.1} ms", millis)?;
+        } else {
+            write!(f, "blocked {:.1} ms ({})", millis, self.time_type.name())?;
+        }
+        if let Some(java_lock) = &self.java_lock {
+            write!(f, " {}", java_lock)?;
+        } else if let Some(info) = &self.info {
+            write!(f, " {}", info)?;
+        }
+        Ok(())
+    }
+}
+
+impl CpuEvent {
+    // log 中每行一条日志，没有时间的日志使用事件的开始时间
+    pub fn log_records(&self) -> Vec<LogRecord> {
+        parse_log_records(&self.log, self.start_time)
+    }
+}
+
 impl TimedEvent for CpuEvent {
     fn start_timestamp(&self) -> u64 {
         self.start_time
@@ -61,87 +225,348 @@ impl TimedEvent for CpuEvent {
         self.end_time
     }
 
-    fn kind(&self) -> i32 {
-        return 0;
+    fn kind(&self) -> &'static str {
+        CPU_EVENT_KIND
+    }
+
+    fn sample_weight(&self) -> f64 {
+        self.sample_weight
+    }
+
+    fn set_sample_weight(&mut self, weight: f64) {
+        self.sample_weight = weight;
+    }
+
+    fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.type_specs.capacity() * std::mem::size_of::<u64>()
+            + self.runq_latency.capacity() * std::mem::size_of::<u64>()
+            + self.time_type.capacity()
+            + self.on_info.capacity()
+            + self.off_info.capacity()
+            + self.log.capacity()
+            + self.stack.capacity()
     }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TimeSegments {
     pub pid: u32,
     pub tid: u32,
     pub thread_name: String,
+    // 按时间排序的线程名变更历史，thread_name 始终为最新的名字
+    pub thread_name_history: Vec<ThreadNameChange>,
+    pub container_id: String,
     pub base_time: u64,
     pub segments: CircleQueue,
+    // 已经发送过的事件。跨多个 segment 的事件在每个 segment 中都有一份，
+    // 导出状态记录在线程上，保证每个事件只在第一次被导出时发送
+    #[serde(default)]
+    sent_events: HashSet<EventKey>,
+    // 最近一次被查询或导出的时间(ns)，内存超出预算时优先淘汰长时间未被查询的线程
+    #[serde(skip)]
+    last_queried: AtomicU64,
 }
 
 impl TimeSegments {
-    fn new(pid: u32, tid: u32, thread_name: String, base_time: u64, segments: CircleQueue) -> Self {
+    pub fn new(pid: u32, tid: u32, thread_name: String, container_id: String, base_time: u64, segments: CircleQueue) -> Self {
         TimeSegments {
             pid,
             tid,
+            thread_name_history: vec![ThreadNameChange { timestamp: base_time * NANO_TO_SECONDS, name: thread_name.clone() }],
             thread_name,
+            container_id,
             base_time,
             segments,
+            sent_events: HashSet::new(),
+            last_queried: AtomicU64::new(0),
+        }
+    }
+
+    // 各 segment 中事件占用的内存之和
+    pub fn bytes(&self) -> usize {
+        self.segments.iter().map(|segment| segment.bytes()).sum()
+    }
+
+    pub fn touch(&self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as u64).unwrap_or(0);
+        self.last_queried.store(now, Ordering::Relaxed);
+    }
+
+    pub fn last_queried(&self) -> u64 {
+        self.last_queried.load(Ordering::Relaxed)
+    }
+
+    pub fn update_thread_name(&mut self, thread_name: &str, timestamp: u64) {
+        let index = self.thread_name_history.partition_point(|change| change.timestamp <= timestamp);
+        let previous = index.checked_sub(1).map(|i| self.thread_name_history[i].name.as_str());
+        if previous != Some(thread_name) {
+            self.thread_name_history.insert(index, ThreadNameChange { timestamp, name: thread_name.to_string() });
+            // 新插入的名字与之后一条相同时，之后那条已没有意义
+            if self.thread_name_history.get(index + 1).is_some_and(|next| next.name == thread_name) {
+                self.thread_name_history.remove(index + 1);
+            }
+        }
+        if let Some(latest) = self.thread_name_history.last() {
+            self.thread_name = latest.name.clone();
+        }
+        self.prune_thread_name_history();
+    }
+
+    // 返回 timestamp 时刻生效的线程名
+    pub fn thread_name_at(&self, timestamp: u64) -> &str {
+        let index = self.thread_name_history.partition_point(|change| change.timestamp <= timestamp);
+        match index.checked_sub(1) {
+            Some(i) => &self.thread_name_history[i].name,
+            None => self.thread_name_history.first().map_or(self.thread_name.as_str(), |change| change.name.as_str()),
+        }
+    }
+
+    // 只保留 base_time 之前的最后一条以及之后的记录
+    fn prune_thread_name_history(&mut self) {
+        let base_time = self.base_time * NANO_TO_SECONDS;
+        let expired = self.thread_name_history.partition_point(|change| change.timestamp <= base_time);
+        if expired > 1 {
+            self.thread_name_history.drain(..expired - 1);
         }
     }
-    pub fn update_thread_name(&mut self, thread_name: &str) {
-        self.thread_name = thread_name.to_string();
+
+    // segment 中尚未发送过的给定类型事件，kinds 为空时包含所有类型
+    pub fn unsent_events_of(&self, segment: &Segment, kinds: &[String]) -> Vec<Arc<dyn TimedEvent>> {
+        segment.events.iter()
+            .filter(|event| includes_kind(kinds, event.kind()) && !self.sent_events.contains(&EventKey::of(event.as_ref())))
+            .cloned()
+            .collect()
+    }
+
+    pub fn mark_sent(&mut self, events: &[Arc<dyn TimedEvent>]) {
+        self.sent_events.extend(events.iter().map(|event| EventKey::of(event.as_ref())));
+    }
+
+    // 重新发送 [start_time, end_time) 内给定类型的事件
+    pub fn reset_sent_between(&mut self, start_time: u64, end_time: u64, kinds: &[String]) {
+        self.sent_events.retain(|key| !includes_kind(kinds, &key.kind) || key.end_time <= start_time || key.start_time >= end_time);
+    }
+
+    // 丢弃已经移出窗口的事件的发送记录
+    pub fn prune_sent_events(&mut self) {
+        let window_start = self.base_time * NANO_TO_SECONDS;
+        self.sent_events.retain(|key| key.end_time > window_start);
+    }
+
+    // 跨多个 segment 的事件在每个 segment 中各存一份引用（快照恢复后则是各自的副本），这里按时间范围去重后返回
+    pub fn events_between(&self, start_time: u64, end_time: u64) -> Vec<&Arc<dyn TimedEvent>> {
+        let mut seen = HashSet::new();
+        let mut events = Vec::new();
+        for segment in self.segments.iter() {
+            if segment.end_time <= start_time || segment.start_time >= end_time {
+                continue;
+            }
+            for event in segment.events.iter() {
+                if event.end_timestamp() <= start_time || event.start_timestamp() >= end_time {
+                    continue;
+                }
+                if seen.insert((event.kind(), event.start_timestamp(), event.end_timestamp())) {
+                    events.push(event);
+                }
+            }
+        }
+        events
+    }
+
+    pub fn events_of_between<T: TimedEvent>(&self, start_time: u64, end_time: u64) -> Vec<&T> {
+        self.events_between(start_time, end_time).into_iter()
+            .filter_map(|event| event.downcast_ref::<T>())
+            .collect()
+    }
+
+    pub fn cpu_events_between(&self, start_time: u64, end_time: u64) -> Vec<&CpuEvent> {
+        self.events_of_between::<CpuEvent>(start_time, end_time)
+    }
+
+    pub fn java_futex_events_between(&self, start_time: u64, end_time: u64) -> Vec<&JavaFutexEvent> {
+        self.events_of_between::<JavaFutexEvent>(start_time, end_time)
+    }
+
+    // 时间范围内按时间排序的 on/off cpu 区间，futex 区间已关联上 java 锁信息
+    pub fn timeline_between(&self, start_time: u64, end_time: u64) -> Vec<CpuInterval> {
+        let futex_events: Vec<JavaFutexEvent> = self.java_futex_events_between(start_time, end_time)
+            .into_iter().cloned().collect();
+        let mut intervals: Vec<CpuInterval> = self.cpu_events_between(start_time, end_time).into_iter()
+            .flat_map(|event| event.intervals())
+            .filter(|interval| interval.overlap(start_time, end_time) > 0)
+            .collect();
+        intervals.sort_by_key(|interval| interval.start_time);
+        correlate_java_futex(&mut intervals, &futex_events);
+        intervals
+    }
+
+    pub fn logs_between(&self, start_time: u64, end_time: u64) -> Vec<LogRecord> {
+        let mut logs: Vec<LogRecord> = self.cpu_events_between(start_time, end_time).into_iter()
+            .flat_map(|event| event.log_records())
+            .filter(|record| record.timestamp >= start_time && record.timestamp < end_time)
+            .collect();
+        logs.sort_by_key(|record| record.timestamp);
+        logs
+    }
+
+    // 与 timeline_between 相同，并按时间插入应用日志
+    pub fn timeline_with_logs_between(&self, start_time: u64, end_time: u64) -> Vec<TimelineEntry> {
+        interleave(self.timeline_between(start_time, end_time), self.logs_between(start_time, end_time))
     }
 }
 
-#[derive(Default, Debug)]
-pub struct Segment {
+// 事件的标识，与 events_between 去重使用的字段相同，快照恢复后的事件副本也能对应上
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct EventKey {
+    kind: String,
     start_time: u64,
     end_time: u64,
-    cpu_events: Vec<CpuEvent>,
-    java_futex_event: Vec<JavaFutexEvent>,
-    pub is_send: i32,
-    pub index_timestamp: String,
 }
 
-impl Clone for Segment {
-    fn clone(&self) -> Self {
-        Segment {
-            start_time: self.start_time,
-            end_time: self.end_time,
-            cpu_events: self.cpu_events.iter().cloned().collect(),
-            java_futex_event: self.java_futex_event.iter().cloned().collect(),
-            is_send: self.is_send,
-            index_timestamp: self.index_timestamp.clone(),
+impl EventKey {
+    fn of(event: &dyn TimedEvent) -> Self {
+        EventKey {
+            kind: event.kind().to_string(),
+            start_time: event.start_timestamp(),
+            end_time: event.end_timestamp(),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadNameChange {
+    pub timestamp: u64,
+    pub name: String,
+}
+
+pub(crate) fn includes_kind(kinds: &[String], kind: &str) -> bool {
+    kinds.is_empty() || kinds.iter().any(|included| included == kind)
+}
+
+const EVENT_REF_SIZE: usize = std::mem::size_of::<Arc<dyn TimedEvent>>();
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    start_time: u64,
+    end_time: u64,
+    // 各种类型的事件按放入顺序保存，跨 segment 的事件共享同一个 Arc
+    #[serde(with = "serde_events")]
+    events: Vec<Arc<dyn TimedEvent>>,
+    // 事件占用的内存，跨多个 segment 的事件只在最后一个 segment 中计入，
+    // 其余 segment 只计引用本身，窗口前移时该事件最后才被释放
+    #[serde(skip)]
+    bytes: usize,
+    // 最近一次导出的时刻(epoch ns)，0 表示尚未导出
+    #[serde(default)]
+    pub index_time: u64,
+}
+
 impl Segment {
     pub fn new(start_time: u64, end_time: u64) -> Self {
         Segment {
             start_time,
             end_time,
-            cpu_events: Vec::new(),
-            java_futex_event: Vec::new(),
-            is_send: 0,
-            index_timestamp: String::new(),
+            events: Vec::new(),
+            bytes: 0,
+            index_time: 0,
         }
     }
-    pub fn put_cpu_event(&mut self, event : CpuEvent) {
-        self.cpu_events.push(event);
 
+    pub fn put_event(&mut self, event: Arc<dyn TimedEvent>) {
+        self.bytes += EVENT_REF_SIZE + event.size_bytes();
+        self.events.push(event);
+    }
+
+    // 放入已经在其他 segment 中计入内存的事件，只计引用本身
+    pub(crate) fn put_shared_event(&mut self, event: Arc<dyn TimedEvent>) {
+        self.bytes += EVENT_REF_SIZE;
+        self.events.push(event);
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // 快照恢复后每个 segment 持有独立的事件副本，按全部大小重新计算
+    pub(crate) fn recompute_bytes(&mut self) {
+        self.bytes = self.events.iter().map(|event| EVENT_REF_SIZE + event.size_bytes()).sum();
+    }
+
+    pub fn start_time(&self) -> u64 {
+        self.start_time
+    }
+
+    pub fn end_time(&self) -> u64 {
+        self.end_time
+    }
+
+    pub fn events(&self) -> &[Arc<dyn TimedEvent>] {
+        &self.events
+    }
+
+    pub fn events_of<T: TimedEvent>(&self) -> impl Iterator<Item = &T> + '_ {
+        self.events.iter().filter_map(|event| event.downcast_ref::<T>())
+    }
+
+    pub fn cpu_events(&self) -> impl Iterator<Item = &CpuEvent> + '_ {
+        self.events_of::<CpuEvent>()
+    }
+
+    pub fn java_futex_events(&self) -> impl Iterator<Item = &JavaFutexEvent> + '_ {
+        self.events_of::<JavaFutexEvent>()
+    }
+
+    pub fn clear_events(&mut self) {
+        *self = Segment::new(self.start_time, self.end_time);
     }
-    pub fn put_java_futex_event(&mut self, event : JavaFutexEvent) {
-        self.java_futex_event.push(event);
 
+    pub fn timeline(&self) -> Vec<CpuInterval> {
+        self.timeline_of(self.cpu_events())
     }
+
+    // 只展开给定的 cpu 事件，但 futex 关联使用本 segment 内全部 java futex 事件
+    pub fn timeline_of<'a, I>(&self, cpu_events: I) -> Vec<CpuInterval> where I: Iterator<Item = &'a CpuEvent> {
+        let mut intervals: Vec<CpuInterval> = cpu_events
+            .flat_map(|event| event.intervals())
+            .filter(|interval| interval.overlap(self.start_time, self.end_time) > 0)
+            .collect();
+        intervals.sort_by_key(|interval| interval.start_time);
+        let futex_events: Vec<JavaFutexEvent> = self.java_futex_events().cloned().collect();
+        correlate_java_futex(&mut intervals, &futex_events);
+        intervals
+    }
+
+    // 给定 cpu 事件中时间落在本 segment 内的日志
+    pub fn logs_of<'a, I>(&self, cpu_events: I) -> Vec<LogRecord> where I: Iterator<Item = &'a CpuEvent> {
+        let mut logs: Vec<LogRecord> = cpu_events
+            .flat_map(|event| event.log_records())
+            .filter(|record| record.timestamp >= self.start_time && record.timestamp < self.end_time)
+            .collect();
+        logs.sort_by_key(|record| record.timestamp);
+        logs
+    }
+
+    pub fn timeline_with_logs(&self) -> Vec<TimelineEntry> {
+        interleave(self.timeline(), self.logs_of(self.cpu_events()))
+    }
+
+    // 任何类型的事件都算作有数据
     pub fn is_not_empty(&self) -> bool {
-        !self.cpu_events.is_empty()
+        !self.events.is_empty()
     }
 
-    pub fn update_index_timestamp(&mut self) {
-        let local_time: DateTime<Local> = Local::now();
-        self.index_timestamp = local_time.to_string();
+    // 记录导出时刻(epoch ns)，导出记录中据此生成 RFC 3339 时间
+    pub fn update_index_time(&mut self) {
+        self.index_time = Utc::now().timestamp_nanos() as u64;
     }
 
 }
@@ -151,6 +576,16 @@ pub struct JavaFutexEvent {
     pub start_time: u64,
     pub end_time: u64,
     pub data_val: String,
+    #[serde(default = "default_sample_weight")]
+    pub sample_weight: f64,
+    // 同 CpuEvent::clock_unreconciled
+    #[serde(default)]
+    pub clock_unreconciled: bool,
+    // 同 CpuEvent::raw_start_time/raw_end_time，开始与结束时间分别来自不同的时钟
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_start_time: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_end_time: Option<u64>,
 }
 
 impl JavaFutexEvent {
@@ -159,8 +594,16 @@ impl JavaFutexEvent {
             start_time: 0,
             end_time: 0,
             data_val: String::new(),
+            sample_weight: 1.0,
+            clock_unreconciled: false,
+            raw_start_time: None,
+            raw_end_time: None,
         }
     }
+
+    pub fn lock_info(&self) -> JavaLockInfo {
+        JavaLockInfo::parse(&self.data_val)
+    }
 }
 
 impl TimedEvent for JavaFutexEvent {
@@ -172,9 +615,26 @@ impl TimedEvent for JavaFutexEvent {
         self.end_time
     }
 
-    fn kind(&self) -> i32 {
-        return 1;
+    fn kind(&self) -> &'static str {
+        JAVA_FUTEX_EVENT_KIND
+    }
+
+    fn sample_weight(&self) -> f64 {
+        self.sample_weight
+    }
+
+    fn set_sample_weight(&mut self, weight: f64) {
+        self.sample_weight = weight;
+    }
+
+    fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data_val.capacity()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
     }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -186,6 +646,10 @@ impl Default for JavaFutexEvent {
             start_time: 0,
             end_time: 0,
             data_val: String::new(),
+            sample_weight: 1.0,
+            clock_unreconciled: false,
+            raw_start_time: None,
+            raw_end_time: None,
         }
     }
 }
@@ -199,3 +663,79 @@ impl fmt::Display for JavaFutexEvent {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+
+    fn seconds(second: u64) -> u64 {
+        (BASE + second) * NANO_TO_SECONDS
+    }
+
+    fn history(time_segments: &TimeSegments) -> Vec<(u64, &str)> {
+        time_segments.thread_name_history.iter()
+            .map(|change| (change.timestamp / NANO_TO_SECONDS - BASE, change.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn thread_name_changes_are_inserted_in_time_order() {
+        let mut time_segments = TimeSegments::new(PID, 10, "a".to_string(), String::new(), BASE, CircleQueue::new(1));
+        time_segments.update_thread_name("c", seconds(20));
+        // 迟到的改名事件插入到中间，当前线程名仍为最新的
+        time_segments.update_thread_name("b", seconds(10));
+        assert_eq!(history(&time_segments), vec![(0, "a"), (10, "b"), (20, "c")]);
+        assert_eq!(time_segments.thread_name, "c");
+
+        // 与前一条同名的不重复记录
+        time_segments.update_thread_name("b", seconds(15));
+        assert_eq!(history(&time_segments).len(), 3);
+        // 提前生效的同名记录替代之后那条
+        time_segments.update_thread_name("c", seconds(18));
+        assert_eq!(history(&time_segments), vec![(0, "a"), (10, "b"), (18, "c")]);
+
+        assert_eq!(time_segments.thread_name_at(seconds(0) - 1), "a");
+        assert_eq!(time_segments.thread_name_at(seconds(9)), "a");
+        assert_eq!(time_segments.thread_name_at(seconds(10)), "b");
+        assert_eq!(time_segments.thread_name_at(seconds(30)), "c");
+    }
+
+    #[test]
+    fn thread_name_history_is_pruned_with_window() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        let put = |thread_name: &str, second: u64| {
+            cca.put_event_to_segments(PID, 10, thread_name, "", Box::new(CpuEvent {
+                start_time: seconds(second),
+                end_time: seconds(second) + 100,
+                type_specs: vec![100],
+                time_type: vec![0],
+                ..Default::default()
+            }));
+        };
+        put("a", 0);
+        put("b", 5);
+        put("c", 30);
+        // 窗口前移半个窗口，base_time 之前只保留最后一条
+        put("c", 45);
+        let pid_events = cca.pid_events(PID).unwrap();
+        {
+            let tid_cpu_events = pid_events.lock().unwrap();
+            let time_segments = &tid_cpu_events.threads[&10];
+            assert_eq!(time_segments.base_time, BASE + 20);
+            assert_eq!(history(time_segments), vec![(5, "b"), (30, "c")]);
+        }
+
+        // 携带旧线程名的迟到事件不改变当前线程名
+        put("b", 25);
+        let tid_cpu_events = pid_events.lock().unwrap();
+        let time_segments = &tid_cpu_events.threads[&10];
+        assert_eq!(history(time_segments), vec![(5, "b"), (30, "c")]);
+        assert_eq!(time_segments.thread_name, "c");
+        assert_eq!(time_segments.thread_name_at(seconds(25)), "b");
+    }
+}
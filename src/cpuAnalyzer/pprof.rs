@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::flame_graph::{StackQuery, StackSample};
+
+// 字段编号参考 https://github.com/google/pprof/blob/main/proto/profile.proto
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const PROFILE_TIME_NANOS: u32 = 9;
+const PROFILE_DURATION_NANOS: u32 = 10;
+const PROFILE_PERIOD_TYPE: u32 = 11;
+const PROFILE_PERIOD: u32 = 12;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const SAMPLE_LABEL: u32 = 3;
+const LABEL_KEY: u32 = 1;
+const LABEL_STR: u32 = 2;
+const LABEL_NUM: u32 = 3;
+const LOCATION_ID: u32 = 1;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_LENGTH_DELIMITED: u32 = 2;
+
+const NO_STACK_FRAME: &str = "[no stack]";
+
+impl CpuAnalyzer {
+    // 导出 gzip 压缩后的 pprof，可直接用 go tool pprof 打开
+    pub fn export_pprof(&self, query: &StackQuery) -> io::Result<Vec<u8>> {
+        let samples = self.stack_samples(query);
+        let profile = encode_profile(&samples, query.start_time, query.end_time);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&profile)?;
+        encoder.finish()
+    }
+}
+
+#[derive(Hash, PartialEq, Eq)]
+struct SampleKey {
+    tid: u32,
+    thread_name: String,
+    off_cpu_reason: Option<&'static str>,
+    frames: Vec<String>,
+}
+
+#[derive(Default)]
+struct ProfileBuilder {
+    strings: Vec<String>,
+    string_index: HashMap<String, u64>,
+    function_ids: HashMap<String, u64>,
+    functions: Vec<u8>,
+    locations: Vec<u8>,
+}
+
+impl ProfileBuilder {
+    fn new() -> Self {
+        let mut builder = ProfileBuilder::default();
+        // string_table[0] 必须为空字符串
+        builder.string("");
+        builder
+    }
+
+    fn string(&mut self, val: &str) -> u64 {
+        if let Some(index) = self.string_index.get(val) {
+            return *index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(val.to_string());
+        self.string_index.insert(val.to_string(), index);
+        index
+    }
+
+    // 每个函数只对应一个 location，两者共用同一个 id
+    fn location(&mut self, frame: &str) -> u64 {
+        if let Some(id) = self.function_ids.get(frame) {
+            return *id;
+        }
+        let id = self.function_ids.len() as u64 + 1;
+        let name = self.string(frame);
+        let mut function = Vec::new();
+        write_varint_field(&mut function, FUNCTION_ID, id);
+        write_varint_field(&mut function, FUNCTION_NAME, name);
+        write_varint_field(&mut function, FUNCTION_SYSTEM_NAME, name);
+        write_bytes_field(&mut self.functions, PROFILE_FUNCTION, &function);
+
+        let mut line = Vec::new();
+        write_varint_field(&mut line, LINE_FUNCTION_ID, id);
+        let mut location = Vec::new();
+        write_varint_field(&mut location, LOCATION_ID, id);
+        write_bytes_field(&mut location, LOCATION_LINE, &line);
+        write_bytes_field(&mut self.locations, PROFILE_LOCATION, &location);
+
+        self.function_ids.insert(frame.to_string(), id);
+        id
+    }
+
+    fn value_type(&mut self, type_name: &str, unit: &str) -> Vec<u8> {
+        let mut value_type = Vec::new();
+        write_varint_field(&mut value_type, VALUE_TYPE_TYPE, self.string(type_name));
+        write_varint_field(&mut value_type, VALUE_TYPE_UNIT, self.string(unit));
+        value_type
+    }
+
+    fn str_label(&mut self, key: &str, val: &str) -> Vec<u8> {
+        let mut label = Vec::new();
+        write_varint_field(&mut label, LABEL_KEY, self.string(key));
+        write_varint_field(&mut label, LABEL_STR, self.string(val));
+        label
+    }
+
+    fn num_label(&mut self, key: &str, val: u64) -> Vec<u8> {
+        let mut label = Vec::new();
+        write_varint_field(&mut label, LABEL_KEY, self.string(key));
+        write_varint_field(&mut label, LABEL_NUM, val);
+        label
+    }
+}
+
+pub fn encode_profile(samples: &[StackSample], start_time: u64, end_time: u64) -> Vec<u8> {
+    let mut aggregated: HashMap<SampleKey, [u64; 2]> = HashMap::new();
+    for sample in samples {
+        let key = SampleKey {
+            tid: sample.tid,
+            thread_name: sample.thread_name.clone(),
+            off_cpu_reason: if sample.time_type.is_on_cpu() { None } else { Some(sample.time_type.name()) },
+            frames: sample.frames.clone(),
+        };
+        let values = aggregated.entry(key).or_insert([0, 0]);
+        if sample.time_type.is_on_cpu() {
+            values[0] += sample.weight;
+        } else {
+            values[1] += sample.weight;
+        }
+    }
+
+    let mut builder = ProfileBuilder::new();
+    let mut profile = Vec::new();
+    let cpu_type = builder.value_type("cpu", "nanoseconds");
+    let off_cpu_type = builder.value_type("off_cpu", "nanoseconds");
+    write_bytes_field(&mut profile, PROFILE_SAMPLE_TYPE, &cpu_type);
+    write_bytes_field(&mut profile, PROFILE_SAMPLE_TYPE, &off_cpu_type);
+
+    for (key, values) in aggregated.iter() {
+        // location_id 由叶到根排列
+        let mut location_ids: Vec<u64> = if key.frames.is_empty() {
+            vec![builder.location(NO_STACK_FRAME)]
+        } else {
+            key.frames.iter().rev().map(|frame| builder.location(frame)).collect()
+        };
+        if let Some(reason) = key.off_cpu_reason {
+            location_ids.insert(0, builder.location(&format!("[off_cpu:{}]", reason)));
+        }
+
+        let mut sample = Vec::new();
+        write_packed_field(&mut sample, SAMPLE_LOCATION_ID, &location_ids);
+        write_packed_field(&mut sample, SAMPLE_VALUE, values);
+        let tid_label = builder.num_label("tid", key.tid as u64);
+        write_bytes_field(&mut sample, SAMPLE_LABEL, &tid_label);
+        let name_label = builder.str_label("thread_name", &key.thread_name);
+        write_bytes_field(&mut sample, SAMPLE_LABEL, &name_label);
+        if let Some(reason) = key.off_cpu_reason {
+            let reason_label = builder.str_label("off_cpu_reason", reason);
+            write_bytes_field(&mut sample, SAMPLE_LABEL, &reason_label);
+        }
+        write_bytes_field(&mut profile, PROFILE_SAMPLE, &sample);
+    }
+
+    let period_type = builder.value_type("cpu", "nanoseconds");
+    profile.extend_from_slice(&builder.locations);
+    profile.extend_from_slice(&builder.functions);
+    for val in builder.strings.iter() {
+        write_bytes_field(&mut profile, PROFILE_STRING_TABLE, val.as_bytes());
+    }
+    write_varint_field(&mut profile, PROFILE_TIME_NANOS, start_time);
+    write_varint_field(&mut profile, PROFILE_DURATION_NANOS, end_time.saturating_sub(start_time));
+    write_bytes_field(&mut profile, PROFILE_PERIOD_TYPE, &period_type);
+    write_varint_field(&mut profile, PROFILE_PERIOD, 1);
+    profile
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut val: u64) {
+    while val >= 0x80 {
+        buf.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    buf.push(val as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(buf, ((field << 3) | wire_type) as u64);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u32, val: u64) {
+    write_key(buf, field, WIRE_VARINT);
+    write_varint(buf, val);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u32, val: &[u8]) {
+    write_key(buf, field, WIRE_LENGTH_DELIMITED);
+    write_varint(buf, val.len() as u64);
+    buf.extend_from_slice(val);
+}
+
+fn write_packed_field(buf: &mut Vec<u8>, field: u32, vals: &[u64]) {
+    let mut packed = Vec::new();
+    for val in vals {
+        write_varint(&mut packed, *val);
+    }
+    write_bytes_field(buf, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::model::CpuTimeType;
+
+    enum FieldValue<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(buf: &[u8], pos: &mut usize) -> u64 {
+        let mut val = 0;
+        let mut shift = 0;
+        loop {
+            let byte = buf[*pos];
+            *pos += 1;
+            val |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return val;
+            }
+            shift += 7;
+        }
+    }
+
+    // 按顺序解出一条消息的所有字段
+    fn decode(buf: &[u8]) -> Vec<(u32, FieldValue<'_>)> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = read_varint(buf, &mut pos);
+            let field = (key >> 3) as u32;
+            match key as u32 & 0x7 {
+                WIRE_VARINT => fields.push((field, FieldValue::Varint(read_varint(buf, &mut pos)))),
+                WIRE_LENGTH_DELIMITED => {
+                    let len = read_varint(buf, &mut pos) as usize;
+                    fields.push((field, FieldValue::Bytes(&buf[pos..pos + len])));
+                    pos += len;
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            }
+        }
+        fields
+    }
+
+    fn varint(fields: &[(u32, FieldValue)], field: u32) -> Vec<u64> {
+        fields.iter().filter_map(|(f, val)| match val {
+            FieldValue::Varint(val) if *f == field => Some(*val),
+            _ => None,
+        }).collect()
+    }
+
+    fn bytes<'a>(fields: &[(u32, FieldValue<'a>)], field: u32) -> Vec<&'a [u8]> {
+        fields.iter().filter_map(|(f, val)| match val {
+            FieldValue::Bytes(val) if *f == field => Some(*val),
+            _ => None,
+        }).collect()
+    }
+
+    fn packed(buf: &[u8]) -> Vec<u64> {
+        let mut vals = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            vals.push(read_varint(buf, &mut pos));
+        }
+        vals
+    }
+
+    fn sample(tid: u32, time_type: CpuTimeType, frames: &[&str], weight: u64) -> StackSample {
+        StackSample {
+            tid,
+            thread_name: "worker".to_string(),
+            time_type,
+            frames: frames.iter().map(|frame| frame.to_string()).collect(),
+            weight,
+        }
+    }
+
+    #[test]
+    fn varint_encoding() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+        let mut pos = 0;
+        assert_eq!(read_varint(&buf, &mut pos), 300);
+    }
+
+    #[test]
+    fn encode_aggregates_samples() {
+        let samples = vec![
+            sample(1, CpuTimeType::On, &["main", "run"], 10),
+            sample(1, CpuTimeType::On, &["main", "run"], 5),
+            sample(1, CpuTimeType::Futex, &["main", "lock"], 7),
+            sample(2, CpuTimeType::On, &[], 3),
+        ];
+        let profile = encode_profile(&samples, 1_000, 4_000);
+        let fields = decode(&profile);
+
+        let strings: Vec<String> = bytes(&fields, PROFILE_STRING_TABLE).iter()
+            .map(|val| String::from_utf8(val.to_vec()).unwrap())
+            .collect();
+        assert_eq!(strings[0], "");
+        assert_eq!(bytes(&fields, PROFILE_SAMPLE_TYPE).len(), 2);
+        assert_eq!(varint(&fields, PROFILE_TIME_NANOS), vec![1_000]);
+        assert_eq!(varint(&fields, PROFILE_DURATION_NANOS), vec![3_000]);
+
+        // location 与 function 共用 id，函数名可从 string_table 查到
+        let mut function_names = HashMap::new();
+        for function in bytes(&fields, PROFILE_FUNCTION) {
+            let function = decode(function);
+            function_names.insert(varint(&function, FUNCTION_ID)[0], strings[varint(&function, FUNCTION_NAME)[0] as usize].clone());
+        }
+        assert_eq!(bytes(&fields, PROFILE_LOCATION).len(), function_names.len());
+
+        let mut decoded: Vec<(Vec<String>, Vec<u64>)> = bytes(&fields, PROFILE_SAMPLE).iter().map(|sample| {
+            let sample = decode(sample);
+            let locations = packed(bytes(&sample, SAMPLE_LOCATION_ID)[0]).iter()
+                .map(|id| function_names[id].clone())
+                .collect();
+            (locations, packed(bytes(&sample, SAMPLE_VALUE)[0]))
+        }).collect();
+        decoded.sort();
+        assert_eq!(decoded, vec![
+            (vec!["[no stack]".to_string()], vec![3, 0]),
+            (vec!["[off_cpu:futex]".to_string(), "lock".to_string(), "main".to_string()], vec![0, 7]),
+            (vec!["run".to_string(), "main".to_string()], vec![15, 0]),
+        ]);
+    }
+}
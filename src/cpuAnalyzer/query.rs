@@ -0,0 +1,216 @@
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::model::{ThreadNameChange, TimeSegments};
+use crate::cpuAnalyzer::sink::SegmentRecord;
+
+// 未设置的条件不做过滤；thread_name 支持 '*' 与 '?' 通配符
+#[derive(Debug, Clone, Default)]
+pub struct SegmentQuery {
+    pub pid: Option<u32>,
+    pub tid: Option<u32>,
+    pub thread_name: Option<String>,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+}
+
+impl SegmentQuery {
+    pub fn matches_thread(&self, time_segments: &TimeSegments) -> bool {
+        if self.pid.is_some_and(|pid| pid != time_segments.pid) {
+            return false;
+        }
+        if self.tid.is_some_and(|tid| tid != time_segments.tid) {
+            return false;
+        }
+        match &self.thread_name {
+            Some(pattern) => wildcard_match(pattern, &time_segments.thread_name)
+                || time_segments.thread_name_history.iter().any(|change| wildcard_match(pattern, &change.name)),
+            None => true,
+        }
+    }
+
+    pub fn overlaps(&self, start_time: u64, end_time: u64) -> bool {
+        self.start_time.is_none_or(|start| end_time > start) && self.end_time.is_none_or(|end| start_time < end)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeSegmentsSnapshot {
+    pub pid: u32,
+    pub tid: u32,
+    pub thread_name: String,
+    pub thread_name_history: Vec<ThreadNameChange>,
+    pub container_id: String,
+    pub base_time: u64,
+    // 只包含与查询时间范围重叠且有数据的 segment
+    pub segments: Vec<SegmentRecord>,
+}
+
+impl CpuAnalyzer {
+    // 只读查询，不会修改发送状态，可与 send_events 并行使用
+    pub fn query_time_segments(&self, query: &SegmentQuery) -> Vec<TimeSegmentsSnapshot> {
+        let mut snapshots = Vec::new();
+        let pid_events = match query.pid {
+            Some(pid) => self.pid_events(pid).map(|events| vec![(pid, events)]).unwrap_or_default(),
+            None => self.all_pid_events(),
+        };
+        let index_timezone = self.index_timezone();
+        for (_, tid_cpu_events) in pid_events {
+            let tid_cpu_events = tid_cpu_events.lock().unwrap();
+            for time_segments in tid_cpu_events.threads.values() {
+                if !query.matches_thread(time_segments) {
+                    continue;
+                }
+                time_segments.touch();
+                let segments: Vec<SegmentRecord> = time_segments.segments.iter()
+                    .filter(|segment| segment.is_not_empty() && query.overlaps(segment.start_time(), segment.end_time()))
+                    .map(|segment| SegmentRecord::new(time_segments, segment, &index_timezone))
+                    .collect();
+                if segments.is_empty() {
+                    continue;
+                }
+                snapshots.push(TimeSegmentsSnapshot {
+                    pid: time_segments.pid,
+                    tid: time_segments.tid,
+                    thread_name: time_segments.thread_name.clone(),
+                    thread_name_history: time_segments.thread_name_history.clone(),
+                    container_id: time_segments.container_id.clone(),
+                    base_time: time_segments.base_time,
+                    segments,
+                });
+            }
+        }
+        snapshots.sort_by_key(|snapshot| (snapshot.pid, snapshot.tid));
+        snapshots
+    }
+
+    pub fn query_segments(&self, query: &SegmentQuery) -> Vec<SegmentRecord> {
+        self.query_time_segments(query).into_iter()
+            .flat_map(|snapshot| snapshot.segments)
+            .collect()
+    }
+
+    pub fn pids(&self) -> Vec<u32> {
+        let mut pids: Vec<u32> = self.all_pid_events().into_iter().map(|(pid, _)| pid).collect();
+        pids.sort_unstable();
+        pids
+    }
+}
+
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut star_text = 0;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some(p);
+            star_text = t;
+            p += 1;
+        } else if let Some(star_pos) = star {
+            p = star_pos + 1;
+            star_text += 1;
+            t = star_text;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cpu_analyzer::NANO_TO_SECONDS;
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+
+    fn put_event(cca: &CpuAnalyzer, tid: u32, thread_name: &str, second: u64) {
+        let start_time = (BASE + second) * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID, tid, thread_name, "", Box::new(CpuEvent {
+            start_time,
+            end_time: start_time + 100,
+            type_specs: vec![100],
+            time_type: vec![0],
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn wildcard_patterns() {
+        assert!(wildcard_match("http-nio-*-exec-?", "http-nio-8080-exec-1"));
+        assert!(!wildcard_match("http-nio-*-exec-?", "http-nio-8080-exec-17"));
+        assert!(wildcard_match("*exec*", "http-nio-8080-exec-17"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("a*b*c", "aXbYbZ"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("**", "anything"));
+        assert!(!wildcard_match("?", ""));
+        assert!(!wildcard_match("", "a"));
+        assert!(wildcard_match("线程-?", "线程-一"));
+    }
+
+    #[test]
+    fn query_filters_threads_and_time() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_event(&cca, 10, "worker-1", 0);
+        put_event(&cca, 10, "worker-1", 2);
+        put_event(&cca, 11, "main", 1);
+
+        let query = SegmentQuery { thread_name: Some("worker-*".to_string()), ..Default::default() };
+        let snapshots = cca.query_time_segments(&query);
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].tid, 10);
+        assert_eq!(snapshots[0].segments.len(), 2);
+
+        let query = SegmentQuery {
+            pid: Some(PID),
+            start_time: Some((BASE + 1) * NANO_TO_SECONDS),
+            end_time: Some((BASE + 2) * NANO_TO_SECONDS),
+            ..Default::default()
+        };
+        let segments = cca.query_segments(&query);
+        assert_eq!(segments.iter().map(|segment| segment.tid).collect::<Vec<_>>(), vec![11]);
+        assert!(cca.query_segments(&SegmentQuery { pid: Some(PID + 1), ..Default::default() }).is_empty());
+    }
+
+    #[test]
+    fn renamed_thread_matches_old_name() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_event(&cca, 10, "pool-1-thread-1", 0);
+        put_event(&cca, 10, "renamed", 1);
+        let query = SegmentQuery { thread_name: Some("pool-*".to_string()), ..Default::default() };
+        let snapshots = cca.query_time_segments(&query);
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].thread_name, "renamed");
+        assert_eq!(snapshots[0].thread_name_history.len(), 2);
+    }
+
+    #[test]
+    fn query_leaves_export_state_unchanged() {
+        let sink = MemorySink::new();
+        let cca = CpuAnalyzer::with_sink(Box::new(sink.clone()));
+        put_event(&cca, 10, "worker", 0);
+        let (start_time, end_time) = (BASE * NANO_TO_SECONDS, (BASE + 1) * NANO_TO_SECONDS);
+
+        assert_eq!(cca.query_segments(&SegmentQuery::default()).len(), 1);
+        assert_eq!(cca.query_segments(&SegmentQuery::default())[0].index_time, 0);
+        // 查询之后事件仍未发送过
+        cca.send_events(PID, start_time, end_time);
+        let sent = sink.take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].cpu_events.len(), 1);
+
+        // 导出之后仍可查询到全部事件
+        let queried = cca.query_segments(&SegmentQuery::default());
+        assert_eq!(queried[0].cpu_events.len(), 1);
+        cca.send_events(PID, start_time, end_time);
+        assert!(sink.take().is_empty());
+    }
+}
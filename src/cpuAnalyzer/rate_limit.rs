@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+// 速率为 0 表示不限制
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    // 每个进程每秒最多保留的事件数
+    pub per_pid_rate: f64,
+    // 每个进程允许的突发事件数，为 0 时取 per_pid_rate
+    pub per_pid_burst: f64,
+    // 所有进程合计每秒最多保留的事件数
+    pub global_rate: f64,
+    pub global_burst: f64,
+}
+
+impl RateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.per_pid_rate > 0.0 || self.global_rate > 0.0
+    }
+
+    pub(crate) fn pid_bucket(&self) -> Option<TokenBucket> {
+        TokenBucket::new(self.per_pid_rate, self.per_pid_burst)
+    }
+
+    pub(crate) fn global_bucket(&self) -> Option<TokenBucket> {
+        TokenBucket::new(self.global_rate, self.global_burst)
+    }
+}
+
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: f64) -> Option<Self> {
+        if rate <= 0.0 {
+            return None;
+        }
+        let burst = if burst > 0.0 { burst } else { rate };
+        Some(TokenBucket {
+            rate,
+            burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+        })
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+    }
+
+    pub fn has_token(&mut self) -> bool {
+        self.refill();
+        self.tokens >= 1.0
+    }
+
+    pub fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+// 单个进程的采样状态。令牌桶按进程共享，被丢弃的事件按 (tid, 事件类型) 累计到
+// 同一线程同类型的下一个保留事件上，因此每个线程每种事件的权重之和等于到达的事件数
+#[derive(Default)]
+pub(crate) struct PidSampler {
+    bucket: Option<TokenBucket>,
+    initialized: bool,
+    // 只保存有未计入权重的丢弃事件的线程
+    skipped: HashMap<(u32, &'static str), u64>,
+}
+
+impl PidSampler {
+    // 返回 None 表示该事件被丢弃，否则返回保留事件的权重。
+    // global 只在进程自身有令牌时才会被消耗
+    pub fn admit(&mut self, tid: u32, kind: &'static str, config: &RateLimitConfig, global: Option<&mut TokenBucket>) -> Option<f64> {
+        if !self.initialized {
+            self.bucket = config.pid_bucket();
+            self.initialized = true;
+        }
+        let pid_allowed = self.bucket.as_mut().is_none_or(|bucket| bucket.has_token());
+        let global_allowed = pid_allowed && match global {
+            Some(global) => {
+                if global.has_token() {
+                    global.take();
+                    true
+                } else {
+                    false
+                }
+            }
+            None => true,
+        };
+        if !global_allowed {
+            *self.skipped.entry((tid, kind)).or_insert(0) += 1;
+            return None;
+        }
+        if let Some(bucket) = self.bucket.as_mut() {
+            bucket.take();
+        }
+        let skipped = self.skipped.remove(&(tid, kind)).unwrap_or(0);
+        Some((skipped + 1) as f64)
+    }
+
+    // 线程被淘汰后丢弃其累计的计数
+    pub fn forget_thread(&mut self, tid: u32) {
+        self.skipped.retain(|(skipped_tid, _), _| *skipped_tid != tid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 速率极低，测试期间不会补充令牌
+    const SLOW_RATE: f64 = 1e-9;
+
+    fn config(per_pid_burst: f64, global_burst: f64) -> RateLimitConfig {
+        RateLimitConfig {
+            per_pid_rate: SLOW_RATE,
+            per_pid_burst,
+            global_rate: if global_burst > 0.0 { SLOW_RATE } else { 0.0 },
+            global_burst,
+        }
+    }
+
+    #[test]
+    fn weights_add_up_to_arrived_events() {
+        let config = config(4.0, 0.0);
+        let mut sampler = PidSampler::default();
+        let bursts = [(1, "cpu_event", 7), (2, "cpu_event", 3), (1, "java_futex", 5)];
+        let mut weights: HashMap<(u32, &'static str), f64> = HashMap::new();
+        // 各线程各类型的事件交替到达，只有前 4 个被保留
+        for round in 0..7 {
+            for (tid, kind, count) in bursts {
+                if round < count {
+                    *weights.entry((tid, kind)).or_default() += sampler.admit(tid, kind, &config, None).unwrap_or(0.0);
+                }
+            }
+        }
+        // 令牌恢复后每个 (tid, 类型) 的下一个保留事件带上之前丢弃的数量
+        for (tid, kind, _) in bursts {
+            sampler.bucket.as_mut().unwrap().tokens = 1.0;
+            *weights.get_mut(&(tid, kind)).unwrap() += sampler.admit(tid, kind, &config, None).unwrap();
+        }
+        for (tid, kind, count) in bursts {
+            assert_eq!(weights[&(tid, kind)], (count + 1) as f64, "tid {} kind {}", tid, kind);
+        }
+        assert!(sampler.skipped.is_empty());
+    }
+
+    #[test]
+    fn global_bucket_only_consumed_when_pid_admits() {
+        let config = config(1.0, 10.0);
+        let mut global = config.global_bucket().unwrap();
+        let mut sampler = PidSampler::default();
+        assert_eq!(sampler.admit(1, "cpu_event", &config, Some(&mut global)), Some(1.0));
+        for _ in 0..5 {
+            assert_eq!(sampler.admit(1, "cpu_event", &config, Some(&mut global)), None);
+        }
+        assert!((global.tokens - 9.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pid_bucket_kept_when_global_rejects() {
+        let config = config(5.0, 1.0);
+        let mut global = config.global_bucket().unwrap();
+        let mut sampler = PidSampler::default();
+        assert_eq!(sampler.admit(1, "cpu_event", &config, Some(&mut global)), Some(1.0));
+        assert_eq!(sampler.admit(1, "cpu_event", &config, Some(&mut global)), None);
+        assert!((sampler.bucket.as_ref().unwrap().tokens - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn forget_thread_drops_skipped_counts() {
+        let config = config(1.0, 0.0);
+        let mut sampler = PidSampler::default();
+        sampler.admit(1, "cpu_event", &config, None);
+        sampler.admit(1, "cpu_event", &config, None);
+        sampler.admit(2, "cpu_event", &config, None);
+        sampler.forget_thread(1);
+        assert_eq!(sampler.skipped.len(), 1);
+        assert_eq!(sampler.skipped[&(2, "cpu_event")], 1);
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::time_event::TimedEvent;
+
+pub(crate) struct PendingEvent {
+    pub thread_name: String,
+    pub container_id: String,
+    pub event: Box<dyn TimedEvent>,
+}
+
+// 单个线程的重排序缓冲，按开始时间有序
+#[derive(Default)]
+pub(crate) struct ReorderBuffer {
+    events: Vec<PendingEvent>,
+    // 该线程见过的最晚事件开始时间，作为本线程的水位，其他线程的时钟偏差不会影响它
+    latest_start_time: u64,
+}
+
+impl ReorderBuffer {
+    // 返回该事件是否早于缓冲中已有的事件(即乱序到达)
+    pub fn push(&mut self, pending: PendingEvent) -> bool {
+        let start_time = pending.event.start_timestamp();
+        self.latest_start_time = self.latest_start_time.max(start_time);
+        let index = self.events.partition_point(|other| other.event.start_timestamp() <= start_time);
+        let out_of_order = index < self.events.len();
+        self.events.insert(index, pending);
+        out_of_order
+    }
+
+    // 取出开始时间不晚于 cutoff 的事件
+    pub fn drain_ready(&mut self, cutoff: u64) -> Vec<PendingEvent> {
+        let count = self.events.partition_point(|pending| pending.event.start_timestamp() <= cutoff);
+        self.events.drain(..count).collect()
+    }
+
+    pub fn latest_start_time(&self) -> u64 {
+        self.latest_start_time
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct AnalyzerCounters {
+    pub out_of_order: AtomicU64,
+    pub late_placed: AtomicU64,
+    pub dropped_too_old: AtomicU64,
+    pub clock_unreconciled: AtomicU64,
+    pub rate_limited: AtomicU64,
+    pub bytes_held: AtomicU64,
+    pub bytes_evicted: AtomicU64,
+    pub threads_evicted: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AnalyzerStats {
+    // 晚于同线程后续事件到达、在缓冲中被重新排序的事件数
+    pub out_of_order: u64,
+    // 开始时间早于当前窗口但结束时间仍在窗口内、被放入已有 segment 的事件数
+    pub late_placed: u64,
+    // 整个事件都早于当前窗口而被丢弃的事件数
+    pub dropped_too_old: u64,
+    // 时间戳无法换算到 realtime 或换算后不合理、以收到时刻估计时间并标记 clock_unreconciled 的事件数
+    pub clock_unreconciled: u64,
+    // 超过进程或全局速率限制而被采样丢弃的事件数
+    pub rate_limited: u64,
+    // 当前 segment 中事件占用的内存(字节)
+    pub bytes_held: u64,
+    // 因超出内存预算而淘汰的线程数及其占用的内存
+    pub bytes_evicted: u64,
+    pub threads_evicted: u64,
+}
+
+impl AnalyzerCounters {
+    pub fn release_bytes(&self, bytes: usize) {
+        let _ = self.bytes_held.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |held| Some(held.saturating_sub(bytes as u64)));
+    }
+
+    pub fn snapshot(&self) -> AnalyzerStats {
+        AnalyzerStats {
+            out_of_order: self.out_of_order.load(Ordering::Relaxed),
+            late_placed: self.late_placed.load(Ordering::Relaxed),
+            dropped_too_old: self.dropped_too_old.load(Ordering::Relaxed),
+            clock_unreconciled: self.clock_unreconciled.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            bytes_held: self.bytes_held.load(Ordering::Relaxed),
+            bytes_evicted: self.bytes_evicted.load(Ordering::Relaxed),
+            threads_evicted: self.threads_evicted.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::cmp::Reverse;
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::cpu_analyzer::{CpuAnalyzer, MAX_SEGMENT_SIZE, NANO_TO_SECONDS};
+use crate::cpuAnalyzer::cgroup::CgroupThrottleEvent;
+use crate::cpuAnalyzer::model::TimeSegments;
+
+// 单个报告最多的窗口数，超出时加大步长
+const MAX_WINDOWS: u64 = 1000;
+
+#[derive(Debug, Clone)]
+pub struct RunqLatencyConfig {
+    // 滑动窗口大小及步长(ns)
+    pub window_size: u64,
+    pub window_step: u64,
+    // 窗口内在运行队列中等待的时间占比超过该值即认为线程处于 CPU 饥饿
+    pub starvation_ratio: f64,
+}
+
+impl Default for RunqLatencyConfig {
+    fn default() -> Self {
+        RunqLatencyConfig {
+            window_size: 5 * NANO_TO_SECONDS,
+            window_step: NANO_TO_SECONDS,
+            starvation_ratio: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyDistribution {
+    pub count: usize,
+    pub total: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+impl LatencyDistribution {
+    pub fn from_samples(samples: &[u64]) -> Self {
+        let weighted: Vec<(u64, f64)> = samples.iter().map(|latency| (*latency, 1.0)).collect();
+        Self::from_weighted_samples(&weighted)
+    }
+
+    // (时延, 采样权重)，count、total 和分位数均按权重放大，还原限流采样前的分布
+    pub fn from_weighted_samples(samples: &[(u64, f64)]) -> Self {
+        if samples.is_empty() {
+            return LatencyDistribution::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable_by_key(|(latency, _)| *latency);
+        let weight: f64 = sorted.iter().map(|(_, weight)| *weight).sum();
+        let total: f64 = sorted.iter().map(|(latency, weight)| *latency as f64 * *weight).sum();
+        LatencyDistribution {
+            count: weight.round() as usize,
+            total: total.round() as u64,
+            min: sorted[0].0,
+            max: sorted[sorted.len() - 1].0,
+            mean: if weight > 0.0 { total / weight } else { 0.0 },
+            p50: percentile(&sorted, weight, 0.5),
+            p90: percentile(&sorted, weight, 0.9),
+            p99: percentile(&sorted, weight, 0.99),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunqWindow {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub latency: LatencyDistribution,
+    // 窗口内在运行队列中等待的时间占窗口长度的比例
+    pub wait_ratio: f64,
+    // 同一容器内在该窗口中处于可运行状态(运行或排队)的线程数
+    pub runnable_threads: usize,
+    // 窗口内所在 cgroup 被 CFS 限流的时长(ns)，按采样间隔比例分摊
+    pub throttled_time: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadRunqLatency {
+    pub tid: u32,
+    pub thread_name: String,
+    pub latency: LatencyDistribution,
+    pub windows: Vec<RunqWindow>,
+    // 与线程数据重叠的 cgroup 限流区间
+    pub throttling: Vec<CgroupThrottleEvent>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StarvedThread {
+    pub tid: u32,
+    pub thread_name: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub wait_ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunqLatencyReport {
+    pub pid: u32,
+    pub container_id: String,
+    // 实际统计的范围，已限制在进程现存的数据之内
+    pub start_time: u64,
+    pub end_time: u64,
+    pub process: LatencyDistribution,
+    pub process_windows: Vec<RunqWindow>,
+    pub threads: Vec<ThreadRunqLatency>,
+    pub starved_threads: Vec<StarvedThread>,
+    // 进程平均排队时延与容器内可运行线程数之间的皮尔逊相关系数
+    pub container_correlation: Option<f64>,
+    // 进程平均排队时延与 cgroup 限流时长之间的皮尔逊相关系数
+    pub throttle_correlation: Option<f64>,
+}
+
+// 单个线程在时间范围内的运行队列采样(采样时间, 等待时长, 采样权重)及 on cpu 区间
+struct ThreadActivity {
+    samples: Vec<(u64, u64, f64)>,
+    on_cpu: Vec<(u64, u64)>,
+    throttling: Vec<CgroupThrottleEvent>,
+}
+
+impl ThreadActivity {
+    fn collect(time_segments: &TimeSegments, start_time: u64, end_time: u64) -> Self {
+        let mut samples = Vec::new();
+        let mut on_cpu = Vec::new();
+        for event in time_segments.cpu_events_between(start_time, end_time) {
+            for interval in event.intervals() {
+                if interval.runq_latency > 0 && interval.start_time >= start_time && interval.start_time < end_time {
+                    samples.push((interval.start_time, interval.runq_latency, interval.sample_weight));
+                }
+                if interval.time_type.is_on_cpu() && interval.overlap(start_time, end_time) > 0 {
+                    on_cpu.push((interval.start_time, interval.end_time));
+                }
+            }
+        }
+        let throttling = time_segments.events_of_between::<CgroupThrottleEvent>(start_time, end_time)
+            .into_iter().cloned().collect();
+        ThreadActivity { samples, on_cpu, throttling }
+    }
+
+    fn throttled_time_in(&self, start_time: u64, end_time: u64) -> u64 {
+        self.throttling.iter().map(|event| event.throttled_time_in(start_time, end_time)).sum()
+    }
+
+    fn samples_in(&self, start_time: u64, end_time: u64) -> Vec<(u64, f64)> {
+        weighted_samples_in(&self.samples, start_time, end_time)
+    }
+
+    fn is_runnable_in(&self, start_time: u64, end_time: u64) -> bool {
+        self.samples.iter().any(|(ts, _, _)| *ts >= start_time && *ts < end_time)
+            || self.on_cpu.iter().any(|(start, end)| *start < end_time && *end > start_time)
+    }
+}
+
+impl CpuAnalyzer {
+    pub fn runq_latency_report(&self, pid: u32, start_time: u64, end_time: u64, config: &RunqLatencyConfig) -> Option<RunqLatencyReport> {
+        let pid_events = self.pid_events(pid)?;
+        let (container_id, own_threads, start_time, end_time) = {
+            let tid_cpu_events = pid_events.lock().unwrap();
+            // 时间范围限制在进程现存的 segment 之内，窗口数不随调用方传入的范围增长
+            let data_start = tid_cpu_events.threads.values()
+                .map(|time_segments| time_segments.base_time.saturating_mul(NANO_TO_SECONDS))
+                .min()?;
+            let data_end = tid_cpu_events.threads.values()
+                .map(|time_segments| time_segments.base_time.saturating_add(MAX_SEGMENT_SIZE as u64).saturating_mul(NANO_TO_SECONDS))
+                .max()?;
+            let (start_time, end_time) = (start_time.max(data_start), end_time.min(data_end));
+            if end_time <= start_time {
+                return None;
+            }
+            let container_id = tid_cpu_events.threads.values()
+                .map(|time_segments| time_segments.container_id.clone())
+                .find(|container_id| !container_id.is_empty())
+                .unwrap_or_default();
+            let own_threads: Vec<(u32, String, ThreadActivity)> = tid_cpu_events.threads.values()
+                .map(|time_segments| (
+                    time_segments.tid,
+                    time_segments.thread_name.clone(),
+                    ThreadActivity::collect(time_segments, start_time, end_time),
+                ))
+                .collect();
+            (container_id, own_threads, start_time, end_time)
+        };
+
+        // 同容器内其他进程的线程(没有容器信息时只统计本进程)
+        let mut other_threads = Vec::new();
+        if !container_id.is_empty() {
+            for (other_pid, other_events) in self.all_pid_events() {
+                if other_pid == pid {
+                    continue;
+                }
+                let other_segments = other_events.lock().unwrap();
+                for time_segments in other_segments.threads.values() {
+                    if time_segments.container_id == container_id {
+                        other_threads.push(ThreadActivity::collect(time_segments, start_time, end_time));
+                    }
+                }
+            }
+        }
+
+        let windows = sliding_windows(start_time, end_time, config);
+        let runnable_threads: Vec<usize> = windows.iter()
+            .map(|(start, end)| {
+                own_threads.iter().map(|(_, _, activity)| activity)
+                    .chain(other_threads.iter())
+                    .filter(|activity| activity.is_runnable_in(*start, *end))
+                    .count()
+            })
+            .collect();
+
+        let mut threads = Vec::new();
+        let mut starved_threads = Vec::new();
+        let mut process_samples: Vec<(u64, u64, f64)> = Vec::new();
+        for (tid, thread_name, activity) in own_threads.iter() {
+            let all_samples: Vec<(u64, f64)> = activity.samples.iter().map(|(_, latency, weight)| (*latency, *weight)).collect();
+            let thread_windows = build_windows(&windows, &runnable_threads, |start, end| activity.samples_in(start, end),
+                |start, end| activity.throttled_time_in(start, end));
+            for window in thread_windows.iter() {
+                if window.wait_ratio > config.starvation_ratio {
+                    starved_threads.push(StarvedThread {
+                        tid: *tid,
+                        thread_name: thread_name.clone(),
+                        start_time: window.start_time,
+                        end_time: window.end_time,
+                        wait_ratio: window.wait_ratio,
+                    });
+                }
+            }
+            process_samples.extend(activity.samples.iter().copied());
+            threads.push(ThreadRunqLatency {
+                tid: *tid,
+                thread_name: thread_name.clone(),
+                latency: LatencyDistribution::from_weighted_samples(&all_samples),
+                windows: thread_windows,
+                throttling: activity.throttling.clone(),
+            });
+        }
+        threads.sort_by_key(|thread| Reverse(thread.latency.total));
+        starved_threads.sort_by(|a, b| b.wait_ratio.partial_cmp(&a.wait_ratio).unwrap());
+
+        // 限流是 cgroup 级别的，进程内每个线程记录的是同一组区间
+        let process_throttling: &[CgroupThrottleEvent] = own_threads.iter()
+            .map(|(_, _, activity)| activity.throttling.as_slice())
+            .max_by_key(|throttling| throttling.len())
+            .unwrap_or(&[]);
+        let process_windows = build_windows(&windows, &runnable_threads, |start, end| weighted_samples_in(&process_samples, start, end),
+            |start, end| process_throttling.iter().map(|event| event.throttled_time_in(start, end)).sum());
+        let means: Vec<f64> = process_windows.iter().map(|window| window.latency.mean).collect();
+        let counts: Vec<f64> = runnable_threads.iter().map(|count| *count as f64).collect();
+        let throttled: Vec<f64> = process_windows.iter().map(|window| window.throttled_time as f64).collect();
+        let all_process_samples: Vec<(u64, f64)> = process_samples.iter().map(|(_, latency, weight)| (*latency, *weight)).collect();
+
+        Some(RunqLatencyReport {
+            pid,
+            container_id,
+            start_time,
+            end_time,
+            process: LatencyDistribution::from_weighted_samples(&all_process_samples),
+            process_windows,
+            threads,
+            starved_threads,
+            container_correlation: pearson_correlation(&means, &counts),
+            throttle_correlation: pearson_correlation(&means, &throttled),
+        })
+    }
+}
+
+fn sliding_windows(start_time: u64, end_time: u64, config: &RunqLatencyConfig) -> Vec<(u64, u64)> {
+    let window_size = config.window_size.max(1);
+    let window_step = config.window_step.max(1).max((end_time - start_time) / MAX_WINDOWS);
+    let mut windows = Vec::new();
+    let mut window_start = start_time;
+    loop {
+        let window_end = window_start.saturating_add(window_size).min(end_time);
+        windows.push((window_start, window_end));
+        if window_end >= end_time {
+            break;
+        }
+        window_start = window_start.saturating_add(window_step);
+    }
+    windows
+}
+
+fn weighted_samples_in(samples: &[(u64, u64, f64)], start_time: u64, end_time: u64) -> Vec<(u64, f64)> {
+    samples.iter()
+        .filter(|(ts, _, _)| *ts >= start_time && *ts < end_time)
+        .map(|(_, latency, weight)| (*latency, *weight))
+        .collect()
+}
+
+fn build_windows<F, T>(windows: &[(u64, u64)], runnable_threads: &[usize], samples_in: F, throttled_time_in: T) -> Vec<RunqWindow>
+    where F: Fn(u64, u64) -> Vec<(u64, f64)>, T: Fn(u64, u64) -> u64 {
+    windows.iter().zip(runnable_threads.iter())
+        .map(|((start, end), runnable)| {
+            let latency = LatencyDistribution::from_weighted_samples(&samples_in(*start, *end));
+            RunqWindow {
+                start_time: *start,
+                end_time: *end,
+                wait_ratio: latency.total as f64 / (end - start).max(1) as f64,
+                latency,
+                runnable_threads: *runnable,
+                throttled_time: throttled_time_in(*start, *end),
+            }
+        })
+        .collect()
+}
+
+fn percentile(sorted: &[(u64, f64)], weight: f64, quantile: f64) -> u64 {
+    let rank = weight * quantile;
+    let mut cumulative = 0.0;
+    for (latency, sample_weight) in sorted {
+        cumulative += sample_weight;
+        if cumulative >= rank {
+            return *latency;
+        }
+    }
+    sorted[sorted.len() - 1].0
+}
+
+fn pearson_correlation(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let n = xs.len().min(ys.len());
+    if n < 3 {
+        return None;
+    }
+    let mean_x = xs[..n].iter().sum::<f64>() / n as f64;
+    let mean_y = ys[..n].iter().sum::<f64>() / n as f64;
+    let mut cov = 0.0;
+    let mut var_x = 0.0;
+    let mut var_y = 0.0;
+    for i in 0..n {
+        let dx = xs[i] - mean_x;
+        let dy = ys[i] - mean_y;
+        cov += dx * dy;
+        var_x += dx * dx;
+        var_y += dy * dy;
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x.sqrt() * var_y.sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn unweighted_percentiles() {
+        let samples: Vec<u64> = (1..=100).collect();
+        let latency = LatencyDistribution::from_samples(&samples);
+        assert_eq!((latency.count, latency.total, latency.min, latency.max), (100, 5050, 1, 100));
+        assert_eq!((latency.p50, latency.p90, latency.p99), (50, 90, 99));
+        assert_eq!(LatencyDistribution::from_samples(&[]).count, 0);
+    }
+
+    #[test]
+    fn weighted_percentiles() {
+        // 权重为 9 的样本代表 9 个被采样丢弃的同类事件
+        let latency = LatencyDistribution::from_weighted_samples(&[(100, 9.0), (10, 1.0)]);
+        assert_eq!(latency.count, 10);
+        assert_eq!(latency.total, 910);
+        assert!((latency.mean - 91.0).abs() < 1e-9);
+        assert_eq!((latency.min, latency.max), (10, 100));
+        assert_eq!((latency.p50, latency.p90, latency.p99), (100, 100, 100));
+
+        let latency = LatencyDistribution::from_weighted_samples(&[(10, 8.0), (100, 2.0)]);
+        assert_eq!((latency.p50, latency.p90), (10, 100));
+    }
+
+    #[test]
+    fn windows_are_capped() {
+        let config = RunqLatencyConfig { window_size: 10, window_step: 1, ..Default::default() };
+        let windows = sliding_windows(0, 100 * NANO_TO_SECONDS, &config);
+        assert!(windows.len() as u64 <= MAX_WINDOWS + 1);
+        assert_eq!(windows[0], (0, 10));
+        assert_eq!(windows[windows.len() - 1].1, 100 * NANO_TO_SECONDS);
+
+        let config = RunqLatencyConfig { window_size: 3, window_step: 2, ..Default::default() };
+        assert_eq!(sliding_windows(0, 6, &config), vec![(0, 3), (2, 5), (4, 6)]);
+        // 结束时间接近上限时不溢出
+        assert_eq!(sliding_windows(u64::MAX - 6, u64::MAX, &config).len(), 3);
+    }
+
+    #[test]
+    fn correlation_requires_variance() {
+        assert_eq!(pearson_correlation(&[1.0, 1.0, 1.0], &[1.0, 2.0, 3.0]), None);
+        assert_eq!(pearson_correlation(&[1.0, 2.0], &[1.0, 2.0]), None);
+        assert!((pearson_correlation(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]).unwrap() - 1.0).abs() < 1e-9);
+        assert!((pearson_correlation(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]).unwrap() + 1.0).abs() < 1e-9);
+    }
+
+    // 每秒开始时排队 runq_ms 毫秒后 on cpu 100ms
+    fn put_thread(cca: &CpuAnalyzer, tid: u32, runq_ms: u64) {
+        for second in 0..3 {
+            let start_time = (BASE + second) * NANO_TO_SECONDS;
+            cca.put_event_to_segments(PID, tid, &format!("worker-{}", tid), "", Box::new(CpuEvent {
+                start_time,
+                end_time: start_time + 100 * MS,
+                type_specs: vec![100 * MS],
+                runq_latency: vec![runq_ms * MS],
+                time_type: vec![0],
+                ..Default::default()
+            }));
+        }
+    }
+
+    #[test]
+    fn starved_threads_are_reported() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 10, 400);
+        put_thread(&cca, 11, 50);
+        let config = RunqLatencyConfig { window_size: NANO_TO_SECONDS, window_step: NANO_TO_SECONDS, starvation_ratio: 0.2 };
+        let report = cca.runq_latency_report(PID, BASE * NANO_TO_SECONDS, (BASE + 3) * NANO_TO_SECONDS, &config).unwrap();
+
+        assert_eq!(report.process_windows.len(), 3);
+        assert_eq!(report.threads[0].tid, 10);
+        assert_eq!(report.threads[0].latency.total, 1200 * MS);
+        assert_eq!(report.process.count, 6);
+        assert_eq!(report.starved_threads.len(), 3);
+        assert!(report.starved_threads.iter().all(|starved| starved.tid == 10 && (starved.wait_ratio - 0.4).abs() < 1e-9));
+        assert!(report.process_windows.iter().all(|window| window.runnable_threads == 2));
+        // 各窗口平均时延相同，相关系数没有意义
+        assert_eq!(report.container_correlation, None);
+    }
+
+    #[test]
+    fn report_range_is_clamped_to_data() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 10, 400);
+        let report = cca.runq_latency_report(PID, 0, u64::MAX, &RunqLatencyConfig::default()).unwrap();
+        assert_eq!(report.start_time, BASE * NANO_TO_SECONDS);
+        assert_eq!(report.end_time, (BASE + MAX_SEGMENT_SIZE as u64) * NANO_TO_SECONDS);
+        assert!(cca.runq_latency_report(PID, 0, BASE * NANO_TO_SECONDS, &RunqLatencyConfig::default()).is_none());
+        assert!(cca.runq_latency_report(PID + 1, 0, u64::MAX, &RunqLatencyConfig::default()).is_none());
+    }
+}
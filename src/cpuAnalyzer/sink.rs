@@ -0,0 +1,323 @@
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use chrono::{FixedOffset, SecondsFormat, TimeZone, Utc};
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::anomaly::Anomaly;
+use crate::cpuAnalyzer::model::{CpuEvent, CpuInterval, JavaFutexEvent, Segment, TimeSegments};
+use crate::cpuAnalyzer::log_record::LogRecord;
+use crate::cpuAnalyzer::time_event::{TimedEvent, CPU_EVENT_KIND, JAVA_FUTEX_EVENT_KIND};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentRecord {
+    pub pid: u32,
+    pub tid: u32,
+    pub thread_name: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub cpu_events: Vec<CpuEvent>,
+    pub java_futex_events: Vec<JavaFutexEvent>,
+    // 内置类型之外通过 register_event_kind 注册的事件
+    pub other_events: Vec<ExportedEvent>,
+    pub timeline: Vec<CpuInterval>,
+    // cpu 事件中解析出的、时间落在本 segment 内的日志，按时间排序
+    pub logs: Vec<LogRecord>,
+    // 包含时钟无法对齐、时间为估计值的事件
+    pub clock_unreconciled: bool,
+    // 导出时刻，epoch ns
+    pub index_time: u64,
+    // 导出时刻的 RFC 3339 UTC 时间，用于按天划分索引
+    pub index_timestamp: String,
+    // 按导出端配置的时区表示的导出时刻
+    pub index_local_timestamp: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedEvent {
+    pub kind: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub data: serde_json::Value,
+}
+
+impl ExportedEvent {
+    pub fn new(event: &dyn TimedEvent) -> Self {
+        ExportedEvent {
+            kind: event.kind().to_string(),
+            start_time: event.start_timestamp(),
+            end_time: event.end_timestamp(),
+            data: event.to_json(),
+        }
+    }
+}
+
+impl SegmentRecord {
+    // index_local_timestamp 按 timezone 表示
+    pub fn new(time_segments: &TimeSegments, segment: &Segment, timezone: &FixedOffset) -> Self {
+        Self::with_events(time_segments, segment, segment.events(), timezone)
+    }
+
+    // 只包含给定的事件，如 TimeSegments::unsent_events_of 的结果
+    pub fn with_events(time_segments: &TimeSegments, segment: &Segment, events: &[Arc<dyn TimedEvent>], timezone: &FixedOffset) -> Self {
+        let mut cpu_events = Vec::new();
+        let mut java_futex_events = Vec::new();
+        let mut other_events = Vec::new();
+        for event in events {
+            match event.kind() {
+                CPU_EVENT_KIND => cpu_events.extend(event.downcast_ref::<CpuEvent>().cloned()),
+                JAVA_FUTEX_EVENT_KIND => java_futex_events.extend(event.downcast_ref::<JavaFutexEvent>().cloned()),
+                _ => other_events.push(ExportedEvent::new(event.as_ref())),
+            }
+        }
+        SegmentRecord {
+            pid: time_segments.pid,
+            tid: time_segments.tid,
+            thread_name: time_segments.thread_name_at(segment.start_time()).to_string(),
+            start_time: segment.start_time(),
+            end_time: segment.end_time(),
+            timeline: segment.timeline_of(cpu_events.iter()),
+            logs: segment.logs_of(cpu_events.iter()),
+            clock_unreconciled: cpu_events.iter().any(|event| event.clock_unreconciled)
+                || java_futex_events.iter().any(|event| event.clock_unreconciled),
+            cpu_events,
+            java_futex_events,
+            other_events,
+            index_time: segment.index_time,
+            index_timestamp: format_index_time(segment.index_time, &Utc),
+            index_local_timestamp: format_index_time(segment.index_time, timezone),
+        }
+    }
+}
+
+fn format_index_time<Tz: TimeZone>(index_time: u64, timezone: &Tz) -> String where Tz::Offset: Display {
+    if index_time == 0 {
+        return String::new();
+    }
+    timezone.timestamp_nanos(index_time as i64).to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+// json lines 输出中每行的 record_type，区分同一流中的 segment 与异常
+pub const SEGMENT_RECORD_TYPE: &str = "segment";
+pub const ANOMALY_RECORD_TYPE: &str = "anomaly";
+
+#[derive(Serialize)]
+struct TypedRecord<'a, T: serde::Serialize> {
+    record_type: &'static str,
+    #[serde(flatten)]
+    record: &'a T,
+}
+
+fn write_json_line<W: Write, T: serde::Serialize>(writer: &mut W, record_type: &'static str, record: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &TypedRecord { record_type, record })?;
+    writer.write_all(b"\n")
+}
+
+pub trait SegmentSink: Send {
+    fn send(&mut self, record: &SegmentRecord) -> io::Result<()>;
+
+    // 默认丢弃异常，只需要 segment 的 sink 不必实现
+    fn send_anomaly(&mut self, _anomaly: &Anomaly) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 每条记录输出为一行 json
+pub struct StdoutJsonSink;
+
+impl SegmentSink for StdoutJsonSink {
+    fn send(&mut self, record: &SegmentRecord) -> io::Result<()> {
+        write_json_line(&mut io::stdout().lock(), SEGMENT_RECORD_TYPE, record)
+    }
+
+    fn send_anomaly(&mut self, anomaly: &Anomaly) -> io::Result<()> {
+        write_json_line(&mut io::stdout().lock(), ANOMALY_RECORD_TYPE, anomaly)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+// 以 json lines 格式追加写入文件
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl SegmentSink for FileSink {
+    fn send(&mut self, record: &SegmentRecord) -> io::Result<()> {
+        write_json_line(&mut self.writer, SEGMENT_RECORD_TYPE, record)
+    }
+
+    fn send_anomaly(&mut self, anomaly: &Anomaly) -> io::Result<()> {
+        write_json_line(&mut self.writer, ANOMALY_RECORD_TYPE, anomaly)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// 保存在内存中，可通过 records() 拿到共享的记录列表
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    records: Arc<Mutex<Vec<SegmentRecord>>>,
+    anomalies: Arc<Mutex<Vec<Anomaly>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    pub fn records(&self) -> Arc<Mutex<Vec<SegmentRecord>>> {
+        Arc::clone(&self.records)
+    }
+
+    pub fn take(&self) -> Vec<SegmentRecord> {
+        std::mem::take(&mut *self.records.lock().unwrap())
+    }
+
+    pub fn take_anomalies(&self) -> Vec<Anomaly> {
+        std::mem::take(&mut *self.anomalies.lock().unwrap())
+    }
+}
+
+impl SegmentSink for MemorySink {
+    fn send(&mut self, record: &SegmentRecord) -> io::Result<()> {
+        self.records.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+
+    fn send_anomaly(&mut self, anomaly: &Anomaly) -> io::Result<()> {
+        self.anomalies.lock().unwrap().push(anomaly.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::anomaly::AnomalyMetric;
+    use crate::cpuAnalyzer::cgroup::CgroupThrottleEvent;
+    use crate::cpuAnalyzer::circle_queue::CircleQueue;
+
+    const NANO: u64 = 1_000_000_000;
+    const BASE: u64 = 1_700_000_000;
+
+    fn cpu_event() -> CpuEvent {
+        CpuEvent {
+            start_time: BASE * NANO,
+            end_time: BASE * NANO + 300,
+            type_specs: vec![100, 200],
+            runq_latency: vec![5, 0],
+            time_type: vec![0, 1],
+            on_info: String::new(),
+            off_info: "read;file=/data/a".to_string(),
+            log: format!("{} INFO hello", BASE * NANO + 150),
+            stack: String::new(),
+            sample_weight: 1.0,
+            clock_unreconciled: false,
+            raw_start_time: None,
+            raw_end_time: None,
+        }
+    }
+
+    fn time_segments() -> TimeSegments {
+        let mut segment = Segment::new(BASE * NANO, (BASE + 1) * NANO);
+        segment.put_event(Arc::new(cpu_event()));
+        segment.put_event(Arc::new(CgroupThrottleEvent {
+            start_time: BASE * NANO,
+            end_time: BASE * NANO + 500,
+            cgroup: "/kubepods/pod1".to_string(),
+            ..Default::default()
+        }));
+        segment.index_time = (BASE + 10) * NANO;
+        let mut segments = CircleQueue::new(1);
+        segments.update_by_index(0, segment);
+        TimeSegments::new(1, 2, "worker".to_string(), String::new(), BASE, segments)
+    }
+
+    fn anomaly() -> Anomaly {
+        Anomaly {
+            pid: 1,
+            tid: 2,
+            thread_name: "worker".to_string(),
+            group: "worker".to_string(),
+            start_time: BASE * NANO,
+            end_time: (BASE + 1) * NANO,
+            metric: AnomalyMetric::OffCpuRatio,
+            value: 0.9,
+            baseline_mean: 0.1,
+            baseline_stddev: 0.05,
+            dominant_off_cpu: None,
+            detail: None,
+        }
+    }
+
+    #[test]
+    fn record_splits_events_by_kind() {
+        let time_segments = time_segments();
+        let segment = time_segments.segments.get_by_index(0).unwrap();
+        let timezone = FixedOffset::east_opt(8 * 3600).unwrap();
+        let record = SegmentRecord::new(&time_segments, segment, &timezone);
+        assert_eq!(record.cpu_events.len(), 1);
+        assert_eq!(record.other_events.len(), 1);
+        assert_eq!(record.other_events[0].kind, "cgroup_throttle");
+        assert_eq!(record.timeline.len(), 2);
+        assert_eq!(record.logs.len(), 1);
+        assert!(!record.clock_unreconciled);
+        assert_eq!(record.index_timestamp, "2023-11-14T22:13:30.000000000Z");
+        assert_eq!(record.index_local_timestamp, "2023-11-15T06:13:30.000000000+08:00");
+    }
+
+    #[test]
+    fn json_lines_carry_record_type() {
+        let time_segments = time_segments();
+        let segment = time_segments.segments.get_by_index(0).unwrap();
+        let record = SegmentRecord::new(&time_segments, segment, &FixedOffset::east_opt(0).unwrap());
+        let mut buf = Vec::new();
+        write_json_line(&mut buf, SEGMENT_RECORD_TYPE, &record).unwrap();
+        write_json_line(&mut buf, ANOMALY_RECORD_TYPE, &anomaly()).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(buf).unwrap().lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["record_type"], "segment");
+        assert_eq!(lines[0]["tid"], 2);
+        assert_eq!(lines[0]["cpu_events"][0]["startTime"], BASE * NANO);
+        assert_eq!(lines[1]["record_type"], "anomaly");
+        assert_eq!(lines[1]["metric"], "OffCpuRatio");
+    }
+
+    #[test]
+    fn memory_sink_keeps_records_and_anomalies() {
+        let time_segments = time_segments();
+        let segment = time_segments.segments.get_by_index(0).unwrap();
+        let record = SegmentRecord::new(&time_segments, segment, &FixedOffset::east_opt(0).unwrap());
+        let mut sink = MemorySink::new();
+        let records = sink.records();
+        sink.send(&record).unwrap();
+        sink.send_anomaly(&anomaly()).unwrap();
+        assert_eq!(records.lock().unwrap().len(), 1);
+        assert_eq!(sink.take().len(), 1);
+        assert!(sink.take().is_empty());
+        assert_eq!(sink.take_anomalies().len(), 1);
+    }
+}
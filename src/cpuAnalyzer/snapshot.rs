@@ -0,0 +1,232 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_derive::{Deserialize, Serialize};
+use crate::cpuAnalyzer::cpu_analyzer::{CpuAnalyzer, MAX_SEGMENT_SIZE, NANO_TO_SECONDS};
+use crate::cpuAnalyzer::model::TimeSegments;
+
+// 2: segment 中的事件改为带类型的统一列表，导出水位按类型记录
+// 3: 导出状态改为按事件记录在线程上，2 中按 segment 记录的水位在加载时忽略
+const SNAPSHOT_VERSION: u32 = 3;
+const MIN_SNAPSHOT_VERSION: u32 = 2;
+
+// 保存时各线程在持有进程锁期间先序列化为 json，加载时直接解析为 TimeSegments
+#[derive(Serialize, Deserialize)]
+struct AnalyzerSnapshot<T = TimeSegments> {
+    version: u32,
+    created_at: u64,
+    time_segments: Vec<T>,
+}
+
+impl CpuAnalyzer {
+    // 先写临时文件再重命名，避免进程中途退出留下不完整的快照
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        let path = path.as_ref();
+        // 逐个进程加锁序列化，不会同时持有多个进程的锁
+        let mut time_segments = Vec::new();
+        for (_, pid_events) in self.all_pid_events() {
+            let tid_cpu_events = pid_events.lock().unwrap();
+            for segments in tid_cpu_events.threads.values() {
+                time_segments.push(serde_json::to_value(segments)?);
+            }
+        }
+        let count = time_segments.len();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, &AnalyzerSnapshot {
+                version: SNAPSHOT_VERSION,
+                created_at: now_nanos(),
+                time_segments,
+            })?;
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(count)
+    }
+
+    // 丢弃保留窗口之外的数据，已存在的线程数据不会被快照覆盖
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        self.load_snapshot_at(path, now_nanos())
+    }
+
+    pub fn load_snapshot_at<P: AsRef<Path>>(&self, path: P, now: u64) -> io::Result<usize> {
+        let reader = BufReader::new(File::open(path)?);
+        let snapshot: AnalyzerSnapshot = serde_json::from_reader(reader)?;
+        if !(MIN_SNAPSHOT_VERSION..=SNAPSHOT_VERSION).contains(&snapshot.version) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported snapshot version {}", snapshot.version)));
+        }
+        for time_segments in snapshot.time_segments.iter() {
+            if let Err(err) = validate(time_segments) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("invalid snapshot data of pid {} tid {}: {}", time_segments.pid, time_segments.tid, err)));
+            }
+        }
+        let retention_start = now.saturating_sub(MAX_SEGMENT_SIZE as u64 * NANO_TO_SECONDS);
+        let mut restored = 0;
+        let mut by_pid: HashMap<u32, Vec<TimeSegments>> = HashMap::new();
+        for mut time_segments in snapshot.time_segments {
+            if !retain_window(&mut time_segments, retention_start) {
+                continue;
+            }
+            by_pid.entry(time_segments.pid).or_default().push(time_segments);
+        }
+        for (pid, threads) in by_pid {
+            let pid_events = self.pid_events_or_insert(pid);
+            let mut tid_cpu_events = pid_events.lock().unwrap();
+            for time_segments in threads {
+                if let Entry::Vacant(entry) = tid_cpu_events.threads.entry(time_segments.tid) {
+                    let time_segments = entry.insert(time_segments);
+                    for i in 0..MAX_SEGMENT_SIZE {
+                        if let Some(segment) = time_segments.segments.get_by_index_mut(i) {
+                            segment.recompute_bytes();
+                        }
+                    }
+                    self.counters.bytes_held.fetch_add(time_segments.bytes() as u64, Ordering::Relaxed);
+                    restored += 1;
+                }
+            }
+        }
+        self.enforce_memory_budget();
+        Ok(restored)
+    }
+}
+
+// 快照文件可能被截断或改动，放入前检查队列结构以及每个 segment 的时间是否与 base_time 对应
+fn validate(time_segments: &TimeSegments) -> Result<(), String> {
+    if !time_segments.segments.is_consistent(MAX_SEGMENT_SIZE) {
+        return Err("malformed segment queue".to_string());
+    }
+    let last_second = time_segments.base_time.checked_add(MAX_SEGMENT_SIZE as u64)
+        .filter(|second| second.checked_mul(NANO_TO_SECONDS).is_some());
+    if last_second.is_none() {
+        return Err(format!("base_time {} out of range", time_segments.base_time));
+    }
+    for i in 0..MAX_SEGMENT_SIZE {
+        let segment = time_segments.segments.get_by_index(i).ok_or("missing segment")?;
+        let start_time = (time_segments.base_time + i as u64) * NANO_TO_SECONDS;
+        if segment.start_time() != start_time || segment.end_time() != start_time + NANO_TO_SECONDS {
+            return Err(format!("segment {} does not match base_time {}", i, time_segments.base_time));
+        }
+    }
+    Ok(())
+}
+
+// 清空保留窗口之前的 segment，全部过期时返回 false
+fn retain_window(time_segments: &mut TimeSegments, retention_start: u64) -> bool {
+    let mut has_events = false;
+    for i in 0..MAX_SEGMENT_SIZE {
+        if let Some(segment) = time_segments.segments.get_by_index_mut(i) {
+            if segment.end_time() <= retention_start {
+                segment.clear_events();
+            } else if !segment.events().is_empty() {
+                has_events = true;
+            }
+        }
+    }
+    has_events
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+
+    fn put_event(cca: &CpuAnalyzer, tid: u32, second: u64) {
+        let start_time = second * NANO_TO_SECONDS;
+        cca.put_event_to_segments(PID, tid, "worker", "", Box::new(CpuEvent {
+            start_time,
+            end_time: start_time + 100,
+            type_specs: vec![100],
+            time_type: vec![0],
+            ..Default::default()
+        }));
+    }
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("snapshot-test-{}-{}.json", name, std::process::id()))
+    }
+
+    fn saved_analyzer(path: &Path) -> CpuAnalyzer {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_event(&cca, 10, BASE);
+        put_event(&cca, 10, BASE + 30);
+        put_event(&cca, 11, BASE + 1);
+        assert_eq!(cca.save_snapshot(path).unwrap(), 2);
+        cca
+    }
+
+    #[test]
+    fn round_trip_trims_to_retention_window() {
+        let path = snapshot_path("round-trip");
+        saved_analyzer(&path);
+
+        // 保留窗口从 BASE + 25 开始，tid 11 的数据全部过期
+        let restored = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        assert_eq!(restored.load_snapshot_at(&path, (BASE + 65) * NANO_TO_SECONDS).unwrap(), 1);
+        let _ = fs::remove_file(&path);
+
+        let pid_events = restored.pid_events(PID).unwrap();
+        let tid_cpu_events = pid_events.lock().unwrap();
+        assert!(!tid_cpu_events.threads.contains_key(&11));
+        let time_segments = &tid_cpu_events.threads[&10];
+        assert_eq!(time_segments.base_time, BASE);
+        let starts: Vec<u64> = time_segments.cpu_events_between(0, u64::MAX).iter().map(|event| event.start_time).collect();
+        assert_eq!(starts, vec![(BASE + 30) * NANO_TO_SECONDS]);
+        assert_eq!(restored.stats().bytes_held, time_segments.bytes() as u64);
+    }
+
+    #[test]
+    fn existing_threads_are_not_overwritten() {
+        let path = snapshot_path("existing");
+        let cca = saved_analyzer(&path);
+        assert_eq!(cca.load_snapshot_at(&path, (BASE + 31) * NANO_TO_SECONDS).unwrap(), 0);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn corrupted_queue_is_rejected() {
+        let path = snapshot_path("corrupted");
+        saved_analyzer(&path);
+        let mut snapshot: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        snapshot["time_segments"][0]["segments"]["head"] = serde_json::json!(MAX_SEGMENT_SIZE + 3);
+        fs::write(&path, snapshot.to_string()).unwrap();
+
+        let restored = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        let err = restored.load_snapshot_at(&path, (BASE + 31) * NANO_TO_SECONDS).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("malformed segment queue"));
+        assert!(restored.pid_events(PID).is_none());
+    }
+
+    #[test]
+    fn shifted_segment_times_are_rejected() {
+        let path = snapshot_path("shifted");
+        saved_analyzer(&path);
+        let mut snapshot: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        snapshot["time_segments"][0]["base_time"] = serde_json::json!(BASE + 5);
+        fs::write(&path, snapshot.to_string()).unwrap();
+
+        let err = CpuAnalyzer::with_sink(Box::new(MemorySink::new()))
+            .load_snapshot_at(&path, (BASE + 31) * NANO_TO_SECONDS).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("does not match base_time"));
+    }
+}
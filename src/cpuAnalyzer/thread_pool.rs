@@ -0,0 +1,249 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use serde_derive::Serialize;
+use crate::cpuAnalyzer::cpu_analyzer::CpuAnalyzer;
+use crate::cpuAnalyzer::model::TimeSegments;
+use crate::cpuAnalyzer::query::wildcard_match;
+use crate::cpuAnalyzer::runq_latency::LatencyDistribution;
+
+// 用户定义的线程池，pattern 支持 '*' 与 '?' 通配符
+#[derive(Debug, Clone)]
+pub struct ThreadPoolPattern {
+    pub name: String,
+    pub pattern: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadPoolConfig {
+    // 按顺序匹配，先匹配上的生效；都不匹配时按归一化后的线程名分组
+    pub patterns: Vec<ThreadPoolPattern>,
+    // on cpu 时间占比达到该值的线程视为忙碌
+    pub busy_threshold: f64,
+}
+
+impl Default for ThreadPoolConfig {
+    fn default() -> Self {
+        ThreadPoolConfig {
+            patterns: Vec::new(),
+            busy_threshold: 0.8,
+        }
+    }
+}
+
+impl ThreadPoolConfig {
+    pub fn pool_name(&self, thread_name: &str) -> String {
+        self.patterns.iter()
+            .find(|pool| wildcard_match(&pool.pattern, thread_name))
+            .map(|pool| pool.name.clone())
+            .unwrap_or_else(|| normalize_thread_name(thread_name))
+    }
+}
+
+// 去掉线程名末尾的编号及其前面的分隔符，如 http-nio-8080-exec-17 -> http-nio-8080-exec
+pub fn normalize_thread_name(thread_name: &str) -> String {
+    let trimmed = thread_name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if trimmed.len() == thread_name.len() {
+        return thread_name.to_string();
+    }
+    let trimmed = trimmed.trim_end_matches(['-', '_', '#', '.', ':', ' ']);
+    if trimmed.is_empty() {
+        thread_name.to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadPoolStats {
+    pub name: String,
+    pub tids: Vec<u32>,
+    pub on_cpu_time: u64,
+    pub off_cpu_time: u64,
+    // 按 off cpu 类型细分的时长
+    pub off_cpu_breakdown: BTreeMap<String, u64>,
+    pub runq_latency: LatencyDistribution,
+    // 池内线程的平均 on cpu 时间占比
+    pub utilization: f64,
+    // on cpu 时间占比达到 busy_threshold 的线程数
+    pub busy_threads: usize,
+    // 运行队列等待时间与 on cpu 时间之比，偏高说明线程数超过了可用 CPU
+    pub runq_ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadPoolReport {
+    pub pid: u32,
+    pub start_time: u64,
+    pub end_time: u64,
+    // 按 on cpu 时间降序
+    pub pools: Vec<ThreadPoolStats>,
+}
+
+#[derive(Default)]
+struct PoolAccumulator {
+    tids: Vec<u32>,
+    on_cpu_time: u64,
+    off_cpu_time: u64,
+    off_cpu_breakdown: BTreeMap<String, u64>,
+    runq_samples: Vec<(u64, f64)>,
+    busy_threads: usize,
+}
+
+impl PoolAccumulator {
+    fn add_thread(&mut self, time_segments: &TimeSegments, start_time: u64, end_time: u64, busy_threshold: f64) {
+        // 时间范围内没有数据的线程不计入，避免拉低利用率
+        let timeline = time_segments.timeline_between(start_time, end_time);
+        if timeline.is_empty() {
+            return;
+        }
+        let mut thread_on_cpu = 0;
+        for interval in timeline {
+            let overlap = interval.weighted(interval.overlap(start_time, end_time));
+            if interval.time_type.is_on_cpu() {
+                thread_on_cpu += overlap;
+            } else {
+                self.off_cpu_time += overlap;
+                *self.off_cpu_breakdown.entry(interval.time_type.name().to_string()).or_insert(0) += overlap;
+            }
+            if interval.runq_latency > 0 {
+                self.runq_samples.push((interval.runq_latency, interval.sample_weight));
+            }
+        }
+        let window = end_time.saturating_sub(start_time);
+        if window > 0 && thread_on_cpu as f64 / window as f64 >= busy_threshold {
+            self.busy_threads += 1;
+        }
+        self.on_cpu_time += thread_on_cpu;
+        self.tids.push(time_segments.tid);
+    }
+
+    fn finish(mut self, name: String, window: u64) -> ThreadPoolStats {
+        self.tids.sort_unstable();
+        let runq_latency = LatencyDistribution::from_weighted_samples(&self.runq_samples);
+        let capacity = window * self.tids.len() as u64;
+        ThreadPoolStats {
+            name,
+            utilization: if capacity > 0 { self.on_cpu_time as f64 / capacity as f64 } else { 0.0 },
+            runq_ratio: if self.on_cpu_time > 0 { runq_latency.total as f64 / self.on_cpu_time as f64 } else { 0.0 },
+            tids: self.tids,
+            on_cpu_time: self.on_cpu_time,
+            off_cpu_time: self.off_cpu_time,
+            off_cpu_breakdown: self.off_cpu_breakdown,
+            runq_latency,
+            busy_threads: self.busy_threads,
+        }
+    }
+}
+
+impl CpuAnalyzer {
+    // 将进程内的线程按线程池分组，汇总 [start_time, end_time) 内的 on/off cpu 时间和饱和度
+    pub fn thread_pool_report(&self, pid: u32, start_time: u64, end_time: u64, config: &ThreadPoolConfig) -> ThreadPoolReport {
+        let mut pools: BTreeMap<String, PoolAccumulator> = BTreeMap::new();
+        if let Some(pid_events) = self.pid_events(pid) {
+            let tid_cpu_events = pid_events.lock().unwrap();
+            for time_segments in tid_cpu_events.threads.values() {
+                let pool_name = config.pool_name(time_segments.thread_name_at(start_time));
+                pools.entry(pool_name).or_default()
+                    .add_thread(time_segments, start_time, end_time, config.busy_threshold);
+            }
+        }
+
+        let window = end_time.saturating_sub(start_time);
+        let mut pools: Vec<ThreadPoolStats> = pools.into_iter()
+            .filter(|(_, pool)| !pool.tids.is_empty())
+            .map(|(name, pool)| pool.finish(name, window))
+            .collect();
+        pools.sort_by_key(|pool| Reverse(pool.on_cpu_time));
+
+        ThreadPoolReport {
+            pid,
+            start_time,
+            end_time,
+            pools,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuAnalyzer::cpu_analyzer::NANO_TO_SECONDS;
+    use crate::cpuAnalyzer::model::CpuEvent;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+    const MS: u64 = 1_000_000;
+
+    fn pattern(name: &str, pattern: &str) -> ThreadPoolPattern {
+        ThreadPoolPattern { name: name.to_string(), pattern: pattern.to_string() }
+    }
+
+    // 以 (类型, 毫秒, 排队毫秒) 描述从 second 秒开始的连续区间
+    fn put_thread(cca: &CpuAnalyzer, tid: u32, thread_name: &str, second: u64, intervals: &[(u8, u64, u64)]) {
+        let start_time = (BASE + second) * NANO_TO_SECONDS;
+        let total: u64 = intervals.iter().map(|(_, duration, _)| duration * MS).sum();
+        cca.put_event_to_segments(PID, tid, thread_name, "", Box::new(CpuEvent {
+            start_time,
+            end_time: start_time + total,
+            type_specs: intervals.iter().map(|(_, duration, _)| duration * MS).collect(),
+            runq_latency: intervals.iter().map(|(_, _, runq)| runq * MS).collect(),
+            time_type: intervals.iter().map(|(time_type, _, _)| *time_type).collect(),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn thread_names_are_normalized() {
+        assert_eq!(normalize_thread_name("http-nio-8080-exec-17"), "http-nio-8080-exec");
+        assert_eq!(normalize_thread_name("pool-1-thread-3"), "pool-1-thread");
+        assert_eq!(normalize_thread_name("C2 CompilerThread0"), "C2 CompilerThread");
+        assert_eq!(normalize_thread_name("Worker#12"), "Worker");
+        assert_eq!(normalize_thread_name("main"), "main");
+        assert_eq!(normalize_thread_name("12345"), "12345");
+    }
+
+    #[test]
+    fn user_patterns_take_precedence() {
+        let config = ThreadPoolConfig {
+            patterns: vec![pattern("tomcat", "http-nio-*-exec-*"), pattern("all-http", "http-*")],
+            ..Default::default()
+        };
+        assert_eq!(config.pool_name("http-nio-8080-exec-17"), "tomcat");
+        assert_eq!(config.pool_name("http-client-3"), "all-http");
+        assert_eq!(config.pool_name("pool-1-thread-3"), "pool-1-thread");
+        assert_eq!(ThreadPoolConfig::default().pool_name("http-nio-8080-exec-17"), "http-nio-8080-exec");
+    }
+
+    #[test]
+    fn pool_utilization_and_busy_threads() {
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        put_thread(&cca, 11, "http-nio-8080-exec-1", 0, &[(0, 900, 0), (1, 100, 0)]);
+        put_thread(&cca, 12, "http-nio-8080-exec-2", 0, &[(0, 500, 100), (3, 500, 0)]);
+        // 范围内没有数据的线程不计入
+        put_thread(&cca, 13, "http-nio-8080-exec-3", 5, &[(0, 1000, 0)]);
+        put_thread(&cca, 20, "main", 0, &[(0, 100, 0)]);
+
+        let report = cca.thread_pool_report(PID, BASE * NANO_TO_SECONDS, (BASE + 1) * NANO_TO_SECONDS, &ThreadPoolConfig::default());
+        assert_eq!(report.pools.iter().map(|pool| pool.name.as_str()).collect::<Vec<_>>(), vec!["http-nio-8080-exec", "main"]);
+
+        let http = &report.pools[0];
+        assert_eq!(http.tids, vec![11, 12]);
+        assert_eq!((http.on_cpu_time, http.off_cpu_time), (1400 * MS, 600 * MS));
+        assert_eq!(http.off_cpu_breakdown.get("file"), Some(&(100 * MS)));
+        assert_eq!(http.off_cpu_breakdown.get("futex"), Some(&(500 * MS)));
+        assert!((http.utilization - 0.7).abs() < 1e-9);
+        assert_eq!(http.busy_threads, 1);
+        assert_eq!(http.runq_latency.count, 1);
+        assert!((http.runq_ratio - 100.0 / 1400.0).abs() < 1e-9);
+
+        let main = &report.pools[1];
+        assert!((main.utilization - 0.1).abs() < 1e-9);
+        assert_eq!((main.busy_threads, main.runq_ratio), (0, 0.0));
+
+        // 降低阈值后两个线程都算忙碌
+        let config = ThreadPoolConfig { busy_threshold: 0.5, ..Default::default() };
+        let report = cca.thread_pool_report(PID, BASE * NANO_TO_SECONDS, (BASE + 1) * NANO_TO_SECONDS, &config);
+        assert_eq!(report.pools[0].busy_threads, 2);
+    }
+}
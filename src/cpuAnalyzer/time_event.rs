@@ -1,16 +1,210 @@
 use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, OnceLock, RwLock};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::cpuAnalyzer::model::{CpuEvent, JavaFutexEvent};
 
-pub trait TimedEvent {
+pub const CPU_EVENT_KIND: &str = "cpu";
+pub const JAVA_FUTEX_EVENT_KIND: &str = "java_futex";
+
+// 可存入 segment 的事件。新增事件类型只需实现该 trait，
+// 并通过 register_event_kind 注册以便从快照中恢复
+pub trait TimedEvent: Any + Debug + Send + Sync {
     fn start_timestamp(&self) -> u64;
     fn end_timestamp(&self) -> u64;
-    fn kind(&self) -> i32;
+    // 事件类型名，导出和快照中以此区分
+    fn kind(&self) -> &'static str;
+    fn to_json(&self) -> Value;
+    // 限流采样后该事件代表的原始事件数
+    fn sample_weight(&self) -> f64 {
+        1.0
+    }
+    fn set_sample_weight(&mut self, _weight: f64) {}
+    // 事件占用的内存(字节)，包含堆上的数据，用于内存预算
+    fn size_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
     fn as_any(&self) -> &dyn Any;
 }
 
-// use crate::cpuAnalyzer::model::{CpuEvent, JavaFutexEvent};
-//
-// pub(crate) enum TimedEvent {
-//     Cpu(CpuEvent),
-//     JavaFutex(JavaFutexEvent),
-//
-// }
\ No newline at end of file
+impl dyn TimedEvent {
+    pub fn downcast_ref<T: TimedEvent>(&self) -> Option<&T> {
+        self.as_any().downcast_ref::<T>()
+    }
+}
+
+type EventDecoder = fn(Value) -> serde_json::Result<Arc<dyn TimedEvent>>;
+
+fn decode<T: TimedEvent + DeserializeOwned>(val: Value) -> serde_json::Result<Arc<dyn TimedEvent>> {
+    Ok(Arc::new(serde_json::from_value::<T>(val)?))
+}
+
+fn event_decoders() -> &'static RwLock<HashMap<&'static str, EventDecoder>> {
+    static DECODERS: OnceLock<RwLock<HashMap<&'static str, EventDecoder>>> = OnceLock::new();
+    DECODERS.get_or_init(|| {
+        let mut decoders: HashMap<&'static str, EventDecoder> = HashMap::new();
+        decoders.insert(CPU_EVENT_KIND, decode::<CpuEvent>);
+        decoders.insert(JAVA_FUTEX_EVENT_KIND, decode::<JavaFutexEvent>);
+        RwLock::new(decoders)
+    })
+}
+
+pub fn register_event_kind<T: TimedEvent + DeserializeOwned>(kind: &'static str) {
+    event_decoders().write().unwrap().insert(kind, decode::<T>);
+}
+
+pub fn decode_event(kind: &str, val: Value) -> Option<Arc<dyn TimedEvent>> {
+    let decoder = *event_decoders().read().unwrap().get(kind)?;
+    match decoder(val) {
+        Ok(event) => Some(event),
+        Err(err) => {
+            eprintln!("failed to decode {} event: {}", kind, err);
+            None
+        }
+    }
+}
+
+// segment 中事件列表的序列化方式：[{"kind": .., "data": ..}]，未注册的类型在恢复时跳过
+pub(crate) mod serde_events {
+    use std::sync::Arc;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::ser::SerializeSeq;
+    use serde_derive::{Deserialize, Serialize};
+    use serde_json::Value;
+    use super::{decode_event, TimedEvent};
+
+    #[derive(Serialize, Deserialize)]
+    struct StoredEvent {
+        kind: String,
+        data: Value,
+    }
+
+    pub fn serialize<S: Serializer>(events: &[Arc<dyn TimedEvent>], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(events.len()))?;
+        for event in events {
+            seq.serialize_element(&StoredEvent {
+                kind: event.kind().to_string(),
+                data: event.to_json(),
+            })?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Arc<dyn TimedEvent>>, D::Error> {
+        let stored = Vec::<StoredEvent>::deserialize(deserializer)?;
+        Ok(stored.into_iter()
+            .filter_map(|event| decode_event(&event.kind, event.data))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+    use crate::cpuAnalyzer::cpu_analyzer::{CpuAnalyzer, NANO_TO_SECONDS};
+    use crate::cpuAnalyzer::query::SegmentQuery;
+    use crate::cpuAnalyzer::sink::MemorySink;
+
+    const PID: u32 = 1;
+    const BASE: u64 = 1_700_000_000;
+    const GC_PAUSE_KIND: &str = "test_gc_pause";
+    const UNREGISTERED_KIND: &str = "test_unregistered";
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct GcPause {
+        start_time: u64,
+        end_time: u64,
+        collector: String,
+    }
+
+    impl TimedEvent for GcPause {
+        fn start_timestamp(&self) -> u64 {
+            self.start_time
+        }
+
+        fn end_timestamp(&self) -> u64 {
+            self.end_time
+        }
+
+        fn kind(&self) -> &'static str {
+            GC_PAUSE_KIND
+        }
+
+        fn to_json(&self) -> Value {
+            serde_json::to_value(self).unwrap_or(Value::Null)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    // 与 GcPause 相同，但不注册
+    #[derive(Debug, Serialize)]
+    struct Unregistered {
+        start_time: u64,
+        end_time: u64,
+    }
+
+    impl TimedEvent for Unregistered {
+        fn start_timestamp(&self) -> u64 {
+            self.start_time
+        }
+
+        fn end_timestamp(&self) -> u64 {
+            self.end_time
+        }
+
+        fn kind(&self) -> &'static str {
+            UNREGISTERED_KIND
+        }
+
+        fn to_json(&self) -> Value {
+            serde_json::to_value(self).unwrap_or(Value::Null)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn registered_kind_survives_snapshot() {
+        register_event_kind::<GcPause>(GC_PAUSE_KIND);
+        let start_time = BASE * NANO_TO_SECONDS;
+        let cca = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        cca.put_event_to_segments(PID, 10, "worker", "", Box::new(GcPause { start_time, end_time: start_time + 100, collector: "G1 Young".to_string() }));
+        cca.put_event_to_segments(PID, 10, "worker", "", Box::new(Unregistered { start_time: start_time + 200, end_time: start_time + 300 }));
+        assert_eq!(cca.query_segments(&SegmentQuery::default())[0].other_events.len(), 2);
+
+        let path = std::env::temp_dir().join(format!("time-event-test-{}.json", std::process::id()));
+        assert_eq!(cca.save_snapshot(&path).unwrap(), 1);
+        let restored = CpuAnalyzer::with_sink(Box::new(MemorySink::new()));
+        assert_eq!(restored.load_snapshot_at(&path, start_time + NANO_TO_SECONDS).unwrap(), 1);
+        let _ = std::fs::remove_file(&path);
+
+        // 未注册的类型在恢复时被跳过
+        let segments = restored.query_segments(&SegmentQuery::default());
+        assert_eq!(segments.len(), 1);
+        let other_events = &segments[0].other_events;
+        assert_eq!(other_events.len(), 1);
+        assert_eq!(other_events[0].kind, GC_PAUSE_KIND);
+        assert_eq!((other_events[0].start_time, other_events[0].end_time), (start_time, start_time + 100));
+        assert_eq!(other_events[0].data["collector"], "G1 Young");
+        assert!(segments[0].cpu_events.is_empty());
+    }
+
+    #[test]
+    fn unregistered_kind_is_not_decoded() {
+        assert!(decode_event(UNREGISTERED_KIND, serde_json::json!({"start_time": 0, "end_time": 1})).is_none());
+        // 已注册但数据不合法时同样跳过
+        register_event_kind::<GcPause>(GC_PAUSE_KIND);
+        assert!(decode_event(GC_PAUSE_KIND, serde_json::json!({"start_time": "x"})).is_none());
+        let event = decode_event(GC_PAUSE_KIND, serde_json::json!({"start_time": 1, "end_time": 2, "collector": "cms"})).unwrap();
+        assert_eq!(event.kind(), GC_PAUSE_KIND);
+        assert_eq!(event.downcast_ref::<GcPause>().unwrap().collector, "cms");
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(dead_code)]
-mod cpuAnalyzer;
+pub mod cpuAnalyzer;
 
 mod probeToRust;
 
@@ -115,6 +115,21 @@ impl KindlingEventForGo {
         String::new()
     }
 
+    pub fn get_container_id(&self) -> String {
+        if let Some(ctx) = self.get_ctx() {
+            if let Some(thread_info) = ctx.get_thread_info() {
+                if thread_info.containerId.is_null() {
+                    return String::new();
+                }
+                let c_str = unsafe { CStr::from_ptr(thread_info.containerId) };
+                if let Ok(str_slice) = c_str.to_str() {
+                    return str_slice.to_string();
+                }
+            }
+        }
+        String::new()
+    }
+
     fn get_ctx(&self) -> Option<&EventContext> {
         Some(&self.context)
     }
@@ -1,15 +1,31 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
+use chrono::Local;
 use crate::probeToRust::kindling_event::{runForGo, startProfile};
-use crate::probeToRust::rust_receiver::{sub_event, getKindlingEvents, get_capture_statistics, catch_signal_up};
+use crate::probeToRust::rust_receiver::{sub_event, getKindlingEvents, get_capture_statistics, catch_signal_up, install_signal_handlers, save_snapshot};
+
+const SNAPSHOT_PATH_ENV: &str = "CPU_ANALYZER_SNAPSHOT";
+const DEFAULT_SNAPSHOT_PATH: &str = "/tmp/kindling_cpu_analyzer_snapshot.json";
+// 等待其他 CPU 上迟到事件的时间
+const REORDER_DELAY: u64 = 200_000_000;
+// 导出记录本地时间使用的时区(相对 UTC 东偏的秒数)，未设置时使用本机时区
+const INDEX_UTC_OFFSET_ENV: &str = "CPU_ANALYZER_INDEX_UTC_OFFSET";
+// 每个进程和全局每秒最多保留的事件数，未设置时不限制
+const PID_EVENT_RATE_ENV: &str = "CPU_ANALYZER_PID_EVENT_RATE";
+const GLOBAL_EVENT_RATE_ENV: &str = "CPU_ANALYZER_GLOBAL_EVENT_RATE";
+// 事件最多占用的内存(字节)，未设置时不限制
+const MEMORY_BUDGET_ENV: &str = "CPU_ANALYZER_MEMORY_BUDGET";
+const ANOMALY_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+const CGROUP_COLLECT_INTERVAL: Duration = Duration::from_secs(1);
 
 mod kindling_event;
 mod rust_receiver;
 
 pub use kindling_event::KindlingEventForGo;
-use crate::cpuAnalyzer::{CpuAnalyzer, print_all_event};
+use crate::cpuAnalyzer::{AnomalyConfig, AnomalyDetector, CgroupCollector, CpuAnalyzer, CpuAnalyzerConfig, RateLimitConfig, StdoutJsonSink, print_all_event};
 
 
 pub fn startProbeToRust() {
@@ -21,9 +37,35 @@ pub fn startProbeToRust() {
     sub_event();
 
     // 初始化on-off cpu分析器
-    let cpu_analyzer = Arc::new(Mutex::new(CpuAnalyzer {
-        cpu_pid_events: HashMap::new(),
-    }));
+    let config = CpuAnalyzerConfig {
+        reorder_delay: REORDER_DELAY,
+        index_utc_offset: env::var(INDEX_UTC_OFFSET_ENV).ok()
+            .and_then(|offset| offset.trim().parse().ok())
+            .unwrap_or_else(|| Local::now().offset().local_minus_utc()),
+        rate_limit: RateLimitConfig {
+            per_pid_rate: env_rate(PID_EVENT_RATE_ENV),
+            global_rate: env_rate(GLOBAL_EVENT_RATE_ENV),
+            ..Default::default()
+        },
+        memory_budget: env::var(MEMORY_BUDGET_ENV).ok()
+            .and_then(|budget| budget.trim().parse().ok())
+            .unwrap_or(0),
+        ..Default::default()
+    };
+    let cpu_analyzer = Arc::new(CpuAnalyzer::with_config(config, Box::new(StdoutJsonSink)));
+
+    // 需在加载快照前创建，以注册限流事件类型
+    let cgroup_collector = CgroupCollector::new(Arc::clone(&cpu_analyzer));
+
+    // 恢复上次退出前保存的数据
+    let snapshot_path = PathBuf::from(env::var(SNAPSHOT_PATH_ENV).unwrap_or_else(|_| DEFAULT_SNAPSHOT_PATH.to_string()));
+    if snapshot_path.exists() {
+        match cpu_analyzer.load_snapshot(&snapshot_path) {
+            Ok(count) => eprintln!("restored {} thread segments from snapshot {}", count, snapshot_path.display()),
+            Err(err) => eprintln!("failed to load snapshot {}: {}", snapshot_path.display(), err),
+        }
+    }
+    install_signal_handlers();
 
     // 启动内核事件统计
     thread::spawn(move || {
@@ -35,7 +77,31 @@ pub fn startProbeToRust() {
         catch_signal_up();
     });
 
+    // 每秒检查新完成的 segment 中的 off cpu 异常
+    let detector = AnomalyDetector::new(Arc::clone(&cpu_analyzer), AnomalyConfig::default());
+    let cpu_analyzer_for_anomaly = Arc::clone(&cpu_analyzer);
+    thread::spawn(move || loop {
+        thread::sleep(ANOMALY_SCAN_INTERVAL);
+        cpu_analyzer_for_anomaly.send_anomalies(&detector.scan());
+    });
+
+    // 定期采集各进程所在 cgroup 的 CFS 限流情况
+    thread::spawn(move || loop {
+        cgroup_collector.collect();
+        thread::sleep(CGROUP_COLLECT_INTERVAL);
+    });
+
     // 开始获取事件
     let cpu_analyzer_clone = Arc::clone(&cpu_analyzer);
-    getKindlingEvents(&cpu_analyzer_clone);
-}
\ No newline at end of file
+    getKindlingEvents(&cpu_analyzer_clone, &snapshot_path);
+
+    // 退出前保存快照
+    cpu_analyzer.flush_all_pending();
+    save_snapshot(&cpu_analyzer, &snapshot_path);
+}
+
+fn env_rate(name: &str) -> f64 {
+    env::var(name).ok()
+        .and_then(|rate| rate.trim().parse().ok())
+        .unwrap_or(0.0)
+}
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 use std::ffi::{c_void, CStr, CString};
 use std::{fmt, slice};
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::cpuAnalyzer::{consume_cpu_event, consume_java_futex_event, CpuAnalyzer};
 use crate::probeToRust::kindling_event::{catchSignalUp, event_params_for_subscribe, getCaptureStatistics, getEventsByInterval, initKindlingEventForGo, KindlingEventForGo, runForGo, startProfile, stopProfile, subEventForGo, SubEvent};
 
@@ -42,7 +44,35 @@ pub fn sub_event() {
 
 }
 
-pub fn getKindlingEvents(ca: &Arc<Mutex<CpuAnalyzer>>) {
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGUSR1 => SNAPSHOT_REQUESTED.store(true, Ordering::SeqCst),
+        _ => SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst),
+    }
+}
+
+// SIGTERM/SIGINT 请求退出，SIGUSR1 请求立即保存一次快照
+pub fn install_signal_handlers() {
+    let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGUSR1, handler);
+    }
+}
+
+pub fn save_snapshot(ca: &CpuAnalyzer, snapshot_path: &Path) {
+    match ca.save_snapshot(snapshot_path) {
+        Ok(count) => eprintln!("saved {} thread segments to snapshot {}", count, snapshot_path.display()),
+        Err(err) => eprintln!("failed to save snapshot {}: {}", snapshot_path.display(), err),
+    }
+}
+
+// 收到退出信号后返回，由调用方保存快照
+pub fn getKindlingEvents(ca: &Arc<CpuAnalyzer>, snapshot_path: &Path) {
     let mut count = 0;
 
     const KEY_VALUE_ARRAY_SIZE: usize = 16;
@@ -57,7 +87,10 @@ pub fn getKindlingEvents(ca: &Arc<Mutex<CpuAnalyzer>>) {
         initKindlingEventForGo(1000, npKindlingEventVoidPtr);
     }
 
-    loop {
+    while !SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+        if SNAPSHOT_REQUESTED.swap(false, Ordering::SeqCst) {
+            save_snapshot(ca, snapshot_path);
+        }
         let res = unsafe { getEventsByInterval(100000000,npKindlingEventVoidPtr , &mut count as *mut _ as *mut libc::c_void) };
         if res == 0 {
             let events = unsafe {
@@ -82,6 +115,7 @@ pub fn getKindlingEvents(ca: &Arc<Mutex<CpuAnalyzer>>) {
                     }
                 }
             }
+            ca.flush_reorder_buffers();
         }
         count = 0;
     }